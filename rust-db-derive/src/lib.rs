@@ -0,0 +1,73 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, spanned::Spanned, Data, DeriveInput, Fields};
+
+/// Derives `HasId` from the field annotated with `#[id]`, its type becomes `HasId::Id`.
+///
+/// ```ignore
+/// #[derive(Serialize, Deserialize, Clone, Debug, HasId)]
+/// struct MyDocument {
+///     #[id]
+///     id: u64,
+///     name: String,
+/// }
+/// ```
+#[proc_macro_derive(HasId, attributes(id))]
+pub fn derive_has_id(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand_has_id(&input) {
+        Ok(expanded) => expanded.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand_has_id(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    input.span(),
+                    "HasId can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.span(),
+                "HasId can only be derived for structs",
+            ))
+        }
+    };
+
+    let mut id_fields = fields
+        .iter()
+        .filter(|field| field.attrs.iter().any(|attr| attr.path().is_ident("id")));
+
+    let id_field = id_fields.next().ok_or_else(|| {
+        syn::Error::new(input.span(), "HasId requires a field annotated with #[id]")
+    })?;
+
+    if let Some(duplicate) = id_fields.next() {
+        return Err(syn::Error::new(
+            duplicate.span(),
+            "only one field can be annotated with #[id]",
+        ));
+    }
+
+    let name = &input.ident;
+    let id_name = &id_field.ident;
+    let id_type = &id_field.ty;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics crate::document::HasId for #name #type_generics #where_clause {
+            type Id = #id_type;
+
+            fn id(&self) -> #id_type {
+                self.#id_name
+            }
+        }
+    })
+}
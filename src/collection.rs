@@ -1,14 +1,232 @@
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{Read, Write};
+use std::ops::{Range, RangeBounds};
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+
 use crate::{
-    collection_file::{CollectionFile, CollectionFileError},
-    collection_indexer::{index_collection_id, IdToPageMap},
-    collection_page::{CollectionPage, CollectionPageError},
-    document::{Document, Filter, HasId},
-    COLLECTION_PAGE_DATA_SIZE,
+    collection_blob::BlobStore,
+    collection_file::{CollectionFile, CollectionFileError, CommitHook, IoStats, OnCommit},
+    collection_history::{DocumentHistory, HistoryError},
+    collection_indexer::{
+        build_sorted_index, index_build_plan, index_collection_id_with_hasher, load_index,
+        read_index, save_index, write_index, IdToPageMap, IndexHasher, SortedIndex,
+    },
+    collection_meta::MetaStore,
+    collection_page::{CollectionPage, CollectionPageError, CollectionPageHeader, FnvHasher},
+    collection_scrub::{scrub_page, ScrubReport},
+    collection_sequence::IdSequence,
+    document::{Comparator, Document, Filter, HasId, Normalizer, Validator},
+    serialization::SerializationConfig,
+    storage::Storage,
 };
 
-struct Collection<T: Document> {
-    id_to_page_map: IdToPageMap<T>,
+/// A collection of documents stored in `{dir}/{name}.collection`.
+///
+/// `Collection` is `Send + Sync`: reads (`find_by_id`, `find_by`, ...) take `&self` and only
+/// do positional reads, so they can run concurrently from several threads. Writes take
+/// `&mut self` and need external synchronization, see `SyncCollection`.
+pub struct Collection<T: Document> {
+    // Unset until the first lookup when the collection is opened with a lazy index.
+    id_to_page_map: OnceLock<IdToPageMap<T, IndexHasher>>,
     collection_file: CollectionFile<T>,
+    validators: Vec<Validator<T>>,
+    normalizer: Option<Normalizer<T>>,
+    pending_batch: Option<Vec<T>>,
+    blob_store: Option<BlobStore>,
+    meta: MetaStore,
+    id_sequence: IdSequence,
+    durable: bool,
+    history: Option<DocumentHistory<T>>,
+    import_options: ImportOptions,
+    index_miss_policy: IndexMissPolicy,
+    verify_unique_on_insert: bool,
+    scan_budget: Option<u64>,
+    max_document_size: Option<u64>,
+    secondary_indexes: HashMap<String, SecondaryIndex<T>>,
+    append_page: u64,
+    ordered_indexes: HashMap<String, Box<dyn OrderedIndex<T>>>,
+    fill_factor: f64,
+    document_cache: Option<DocumentCache<T>>,
+    default_order: Option<Comparator<T>>,
+}
+
+/// Documents found by `find_by_id`, up to `capacity` of them, a full cache evicts an
+/// arbitrary document. Entries are dropped when their document is updated or deleted.
+struct DocumentCache<T: HasId> {
+    capacity: usize,
+    documents: Mutex<HashMap<<T as HasId>::Id, T>>,
+}
+
+type IndexKey<T> = Box<dyn Fn(&T) -> Result<Vec<u8>, Box<bincode::ErrorKind>> + Send + Sync>;
+
+/// A named key derived from documents. When built, `pages` maps every encoded key to the
+/// pages that held a document with that key, which can be more pages than hold it now.
+struct SecondaryIndex<T> {
+    key: IndexKey<T>,
+    pages: Option<HashMap<Vec<u8>, BTreeSet<u64>>>,
+}
+
+/// A sorted index, hidden behind a trait so indexes over different key types can be kept
+/// in the same map. `range` downcasts it back to `BTreeIndex` to query it.
+trait OrderedIndex<T>: Send + Sync {
+    fn clear(&mut self);
+    fn insert(&mut self, doc: &T, page_number: u64);
+    fn as_any(&self) -> &dyn Any;
+}
+
+/// Maps every key to the pages that held a document with that key, like `SecondaryIndex`
+/// but ordered by the key itself instead of its encoding.
+struct BTreeIndex<T, K> {
+    key: fn(&T) -> K,
+    pages: BTreeMap<K, BTreeSet<u64>>,
+}
+
+impl<T: 'static, K: Ord + Send + Sync + 'static> OrderedIndex<T> for BTreeIndex<T, K> {
+    fn clear(&mut self) {
+        self.pages.clear();
+    }
+
+    fn insert(&mut self, doc: &T, page_number: u64) {
+        self.pages
+            .entry((self.key)(doc))
+            .or_default()
+            .insert(page_number);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicatePolicy {
+    Fail,
+    Skip,
+}
+
+/// Controls `import_iter`: how often progress is reported and what to do with duplicates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImportOptions {
+    pub progress_interval: usize,
+    pub on_duplicate: DuplicatePolicy,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            progress_interval: 100,
+            on_duplicate: DuplicatePolicy::Fail,
+        }
+    }
+}
+
+/// Merges runs of documents sorted by id into a single stream sorted by id.
+struct SortedMerge<T: HasId> {
+    runs: Vec<std::vec::IntoIter<T>>,
+    heads: BinaryHeap<Reverse<(<T as HasId>::Id, usize)>>,
+    next_documents: Vec<Option<T>>,
+}
+
+impl<T: HasId> SortedMerge<T>
+where
+    <T as HasId>::Id: Ord,
+{
+    fn new(runs: Vec<Vec<T>>) -> Self {
+        let mut merge = SortedMerge {
+            runs: runs.into_iter().map(|run| run.into_iter()).collect(),
+            heads: BinaryHeap::new(),
+            next_documents: vec![],
+        };
+        for run in 0..merge.runs.len() {
+            merge.next_documents.push(None);
+            merge.advance(run);
+        }
+
+        merge
+    }
+
+    fn advance(&mut self, run: usize) {
+        if let Some(document) = self.runs[run].next() {
+            self.heads.push(Reverse((document.id(), run)));
+            self.next_documents[run] = Some(document);
+        }
+    }
+}
+
+impl<T: HasId> Iterator for SortedMerge<T>
+where
+    <T as HasId>::Id: Ord,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        let Reverse((_, run)) = self.heads.pop()?;
+        let document = self.next_documents[run].take();
+        self.advance(run);
+
+        document
+    }
+}
+
+/// What `try_find_by_id` does when the page the index points to doesn't hold the document,
+/// which means the index is out of date.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexMissPolicy {
+    ReturnNone,
+    Rescan,
+    Error,
+}
+
+/// Result of `find_by_id_checked`, `healed` is set when a stale index entry was fixed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckedFind<T> {
+    pub document: Option<T>,
+    pub healed: bool,
+}
+
+/// Result of `diff`: ids stored only on one side, and ids stored on both with different content.
+pub struct CollectionDiff<T: HasId> {
+    pub only_in_self: Vec<<T as HasId>::Id>,
+    pub only_in_other: Vec<<T as HasId>::Id>,
+    pub changed: Vec<<T as HasId>::Id>,
+}
+
+/// An id stored on more than one page, with the pages holding it.
+type DuplicateId<T> = (<T as HasId>::Id, Vec<u64>);
+
+type PageMigration<T> = Box<dyn Fn(&CollectionFile<T>, u64) -> Result<Vec<T>, CollectionError>>;
+type IndexLoader<T> = Box<
+    dyn Fn(&CollectionFile<T>) -> Result<Option<IdToPageMap<T, IndexHasher>>, CollectionFileError>,
+>;
+
+pub struct CollectionBuilder<T: Document> {
+    name: String,
+    dir: String,
+    serialization_config: SerializationConfig,
+    migration: Option<PageMigration<T>>,
+    storage: Option<Box<dyn Storage>>,
+    durable: bool,
+    versioning: bool,
+    max_document_size: Option<u64>,
+    fill_factor: f64,
+    slot_directory: bool,
+    page_size: Option<u64>,
+    create_first_page: bool,
+    lazy_index: bool,
+    page_cache: Option<usize>,
+    document_cache: Option<usize>,
+    write_back: bool,
+    index_loader: Option<IndexLoader<T>>,
+    on_commit: Option<CommitHook>,
 }
 
 #[derive(Debug)]
@@ -16,9 +234,17 @@ pub enum CollectionError {
     FileError(CollectionFileError),
     PageError(CollectionPageError),
     NotFoundError,
+    StaleIndexError,
     DocumentTooBig,
     DuplicateError,
+    IdMismatchError,
+    ValidationFailed(String),
+    SchemaVersionMismatch,
+    VersioningDisabled,
+    HistoryError(HistoryError),
     SerializeError(Box<bincode::ErrorKind>),
+    CodecNotSelfDescribingError,
+    ScanBudgetExceededError,
 }
 
 impl From<CollectionFileError> for CollectionError {
@@ -31,21 +257,447 @@ impl From<CollectionPageError> for CollectionError {
         CollectionError::PageError(err)
     }
 }
+impl From<HistoryError> for CollectionError {
+    fn from(err: HistoryError) -> Self {
+        CollectionError::HistoryError(err)
+    }
+}
 impl From<Box<bincode::ErrorKind>> for CollectionError {
     fn from(err: Box<bincode::ErrorKind>) -> Self {
         CollectionError::SerializeError(err)
     }
 }
 
-impl<T: Document> Collection<T> {
-    fn new(name: &str, dir: &str) -> Collection<T> {
-        let collection_file = CollectionFile::new(name, dir).unwrap();
-        let collection_id_idx = index_collection_id(&collection_file).unwrap();
+impl<T: Document> CollectionBuilder<T> {
+    fn with_serialization_config(mut self, serialization_config: SerializationConfig) -> Self {
+        self.serialization_config = serialization_config;
+        self
+    }
+
+    /// Upgrades documents stored with an older `schema_version` when the collection is built.
+    fn with_migration<O: Document + 'static>(mut self, migration: Box<dyn Fn(O) -> T>) -> Self
+    where
+        T: 'static,
+    {
+        self.migration = Some(Box::new(move |collection_file, page_number| {
+            let old_page = collection_file.read_page_as::<O>(page_number)?;
+
+            Ok(old_page
+                .documents()
+                .iter()
+                .map(|document| migration(document.clone()))
+                .collect())
+        }));
+        self
+    }
+
+    /// Uses the given storage instead of opening `{dir}/{name}.collection`.
+    fn with_storage(mut self, storage: Box<dyn Storage>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Syncs data to disk between the writes of a multi page operation, so that a crash
+    /// can't lose a document being moved from one page to another.
+    fn with_durability(mut self, durable: bool) -> Self {
+        self.durable = durable;
+        self
+    }
+
+    /// Rejects documents whose serialized size exceeds `max_document_size` bytes with
+    /// `DocumentTooBig`, even when they would fit in a page.
+    fn with_max_document_size(mut self, max_document_size: u64) -> Self {
+        self.max_document_size = Some(max_document_size);
+        self
+    }
+
+    /// Page size of a new collection file. An existing file must have been created with the
+    /// same page size, without this option its stored page size is used.
+    fn with_page_size(mut self, page_size: u64) -> Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    /// Loads the index saved by `flush_index` instead of rescanning the pages, when it is
+    /// still up to date.
+    fn with_persistent_index(mut self) -> Self
+    where
+        <T as HasId>::Id: DeserializeOwned,
+    {
+        self.index_loader = Some(Box::new(|collection_file| load_index(collection_file)));
+        self
+    }
+
+    /// Calls `on_commit` with the page number and bytes of every page write, once the write
+    /// has been synced to disk.
+    fn with_on_commit(mut self, on_commit: OnCommit) -> Self {
+        self.on_commit = Some(CommitHook(on_commit));
+        self
+    }
+
+    /// Share of a page new documents can fill, between 0 and 1. The rest is kept for
+    /// documents of the page growing on update, so they don't have to move to another page.
+    fn with_fill_factor(mut self, fill_factor: f64) -> Self {
+        self.fill_factor = fill_factor.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Gives new pages a slot directory sorted by id hash, so a document is found in its
+    /// page by binary search. Costs a few bytes per document.
+    fn with_slot_directory(mut self) -> Self {
+        self.slot_directory = true;
+        self
+    }
+
+    /// Leaves a new collection file without pages instead of writing an empty page 0, the
+    /// first insert creates it.
+    fn without_first_page(mut self) -> Self {
+        self.create_first_page = false;
+        self
+    }
+
+    /// Opens the collection without scanning its pages, the id index is built by the first
+    /// operation needing it (lookup by id, duplicate check, ...). Suits append mostly workloads.
+    fn with_lazy_index(mut self) -> Self {
+        self.lazy_index = true;
+        self
+    }
+
+    /// Keeps up to `capacity` pages in memory, see `CollectionFile::warm_cache` to fill it
+    /// before the first queries.
+    fn with_page_cache(mut self, capacity: usize) -> Self {
+        self.page_cache = Some(capacity);
+        self
+    }
+
+    /// Keeps up to `capacity` documents found by `find_by_id` in memory, so lookups of hot
+    /// ids skip reading and decoding their page. Independent of the page cache.
+    fn with_document_cache(mut self, capacity: usize) -> Self {
+        self.document_cache = Some(capacity);
+        self
+    }
+
+    /// Keeps every page in memory and writes them back to the file on `flush`, when the
+    /// collection is dropped or synced. Mutations only touch memory in between.
+    fn with_write_back(mut self) -> Self {
+        self.write_back = true;
+        self
+    }
+
+    /// Keeps past versions of updated documents in a history sidecar collection.
+    fn with_versioning(mut self, versioning: bool) -> Self {
+        self.versioning = versioning;
+        self
+    }
+
+    pub fn build(self) -> Result<Collection<T>, CollectionError> {
+        let mut collection_file = match self.storage {
+            Some(storage) => CollectionFile::from_storage(
+                &self.name,
+                &self.dir,
+                storage,
+                self.serialization_config,
+                self.page_size,
+                self.create_first_page,
+            )?,
+            None => CollectionFile::open_with_page_size(
+                &self.name,
+                &self.dir,
+                self.serialization_config,
+                self.page_size,
+                self.create_first_page,
+            )?,
+        };
+        collection_file.set_slot_directory(self.slot_directory);
+        if let Some(capacity) = self.page_cache {
+            collection_file.set_page_cache(capacity);
+        }
+        if self.write_back {
+            collection_file.set_write_back()?;
+        }
+        if let Some(on_commit) = self.on_commit {
+            collection_file.set_on_commit(on_commit);
+        }
+        let mut relocated_documents = vec![];
+
+        for page_number in 0..collection_file.number_of_pages() {
+            let schema_version = collection_file
+                .read_page_header(page_number)?
+                .schema_version();
+
+            if schema_version == T::schema_version() {
+                continue;
+            }
+
+            let migration = match &self.migration {
+                Some(migration) if schema_version < T::schema_version() => migration,
+                _ => return Err(CollectionError::SchemaVersionMismatch),
+            };
 
-        Collection {
+            let mut page = collection_file.new_page(page_number);
+            for document in migration(&collection_file, page_number)? {
+                match page.insert_document(&document) {
+                    Ok(_) => {}
+                    Err(CollectionPageError::NoFreeSpaceAvailable) => {
+                        relocated_documents.push(document)
+                    }
+                    Err(e) => return Err(CollectionError::PageError(e)),
+                }
+            }
+
+            collection_file.write_page(&page)?;
+        }
+
+        let saved_index = match self.index_loader.as_ref() {
+            Some(load) => load(&collection_file)?,
+            None => None,
+        };
+        let collection_id_idx = match saved_index {
+            Some(index) => OnceLock::from(index),
+            None if self.lazy_index => OnceLock::new(),
+            None => OnceLock::from(index_collection_id_with_hasher(&collection_file)?),
+        };
+        let blob_store = if Path::new(&format!("{}/{}.blob", self.dir, self.name)).exists() {
+            Some(BlobStore::open(&self.name, &self.dir)?)
+        } else {
+            None
+        };
+        let history = if self.versioning {
+            Some(DocumentHistory::open(
+                &self.name,
+                &self.dir,
+                self.serialization_config,
+            )?)
+        } else {
+            None
+        };
+        let mut collection = Collection {
             id_to_page_map: collection_id_idx,
             collection_file,
+            validators: vec![],
+            normalizer: None,
+            pending_batch: None,
+            blob_store,
+            meta: MetaStore::open(&self.name, &self.dir)?,
+            id_sequence: IdSequence::open(&self.name, &self.dir),
+            durable: self.durable,
+            history,
+            import_options: ImportOptions::default(),
+            index_miss_policy: IndexMissPolicy::ReturnNone,
+            verify_unique_on_insert: false,
+            scan_budget: None,
+            max_document_size: self.max_document_size,
+            secondary_indexes: HashMap::new(),
+            append_page: 0,
+            ordered_indexes: HashMap::new(),
+            fill_factor: self.fill_factor,
+            document_cache: self.document_cache.map(|capacity| DocumentCache {
+                capacity,
+                documents: Mutex::new(HashMap::new()),
+            }),
+            default_order: None,
+        };
+
+        for document in relocated_documents.iter() {
+            collection.insert_one(document)?;
+        }
+
+        Ok(collection)
+    }
+}
+
+impl<T: Document> Collection<T> {
+    pub fn new(name: &str, dir: &str) -> Collection<T> {
+        Self::builder(name, dir).build().unwrap()
+    }
+
+    pub fn builder(name: &str, dir: &str) -> CollectionBuilder<T> {
+        CollectionBuilder {
+            name: name.to_string(),
+            dir: dir.to_string(),
+            serialization_config: SerializationConfig::default(),
+            migration: None,
+            storage: None,
+            durable: false,
+            versioning: false,
+            max_document_size: None,
+            fill_factor: 1.0,
+            slot_directory: false,
+            page_size: None,
+            create_first_page: true,
+            lazy_index: false,
+            page_cache: None,
+            document_cache: None,
+            write_back: false,
+            index_loader: None,
+            on_commit: None,
+        }
+    }
+
+    /// Registers a validator run on every `insert_one`, `update_one` and `upsert_one`.
+    /// Validators are called in registration order, the first error aborts the write.
+    fn with_validator(mut self, validator: Validator<T>) -> Self {
+        self.validators.push(validator);
+        self
+    }
+
+    /// Sets a hook applied to documents before they are validated and stored,
+    /// used to keep stored data canonical (trimmed, lowercased, ...).
+    fn with_normalizer(mut self, normalizer: Normalizer<T>) -> Self {
+        self.normalizer = Some(normalizer);
+        self
+    }
+
+    fn with_import_options(mut self, import_options: ImportOptions) -> Self {
+        self.import_options = import_options;
+        self
+    }
+
+    fn with_index_miss_policy(mut self, index_miss_policy: IndexMissPolicy) -> Self {
+        self.index_miss_policy = index_miss_policy;
+        self
+    }
+
+    /// Scans every page for the id of a document before inserting it when the index doesn't
+    /// have it, so inserts stay unique while the index may be stale, for example during a
+    /// recovery. Makes every insert read the whole collection.
+    fn with_verify_unique_on_insert(mut self, verify_unique_on_insert: bool) -> Self {
+        self.verify_unique_on_insert = verify_unique_on_insert;
+        self
+    }
+
+    /// Caps the serialized size, in bytes, of the documents a `find_by` scan collects.
+    /// A scan going over it fails with `ScanBudgetExceededError` instead of growing further.
+    fn with_scan_budget(mut self, scan_budget: u64) -> Self {
+        self.scan_budget = Some(scan_budget);
+        self
+    }
+
+    /// Registers a named key for `find_by_using_index` without building an index for it,
+    /// lookups by this key scan every page.
+    fn with_index_key<K: Serialize + 'static>(mut self, name: &str, key: fn(&T) -> K) -> Self
+    where
+        T: 'static,
+    {
+        self.secondary_indexes.insert(
+            name.to_string(),
+            SecondaryIndex {
+                key: Box::new(move |document| bincode::serialize(&key(document))),
+                pages: None,
+            },
+        );
+        self
+    }
+
+    /// Registers a named key and builds an index from its values to the pages holding them,
+    /// kept up to date on writes, so `find_by_using_index` only reads matching pages.
+    fn with_secondary_index<K: Serialize + 'static>(
+        self,
+        name: &str,
+        key: fn(&T) -> K,
+    ) -> Result<Self, CollectionError>
+    where
+        T: 'static,
+    {
+        let mut collection = self.with_index_key(name, key);
+        if let Some(index) = collection.secondary_indexes.get_mut(name) {
+            index.pages = Some(HashMap::new());
+        }
+        collection.build_secondary_indexes()?;
+
+        Ok(collection)
+    }
+
+    /// Builds a sorted index from the values of `key` to the pages holding them, kept up to
+    /// date on writes, so `range` only reads pages with documents in the queried range.
+    fn with_ordered_index<K: Ord + Send + Sync + 'static>(
+        mut self,
+        name: &str,
+        key: fn(&T) -> K,
+    ) -> Result<Self, CollectionError>
+    where
+        T: 'static,
+    {
+        self.ordered_indexes.insert(
+            name.to_string(),
+            Box::new(BTreeIndex {
+                key,
+                pages: BTreeMap::new(),
+            }),
+        );
+        self.build_secondary_indexes()?;
+
+        Ok(self)
+    }
+
+    /// The id to page index, built by scanning every page the first time when it is lazy.
+    fn id_index(&self) -> Result<&IdToPageMap<T, IndexHasher>, CollectionError> {
+        if let Some(index) = self.id_to_page_map.get() {
+            return Ok(index);
+        }
+
+        let index = index_collection_id_with_hasher(&self.collection_file)?;
+        Ok(self.id_to_page_map.get_or_init(|| index))
+    }
+
+    /// Page numbers a full rebuild of the id index reads, to estimate its cost.
+    fn index_build_plan(&self) -> Vec<u64> {
+        index_build_plan(&self.collection_file)
+    }
+
+    fn build_secondary_indexes(&mut self) -> Result<(), CollectionError> {
+        for index in self.secondary_indexes.values_mut() {
+            if let Some(pages) = index.pages.as_mut() {
+                pages.clear();
+            }
+        }
+        for index in self.ordered_indexes.values_mut() {
+            index.clear();
+        }
+
+        for page_number in 0..self.collection_file.number_of_pages() {
+            let page = self.collection_file.read_page(page_number)?;
+            for document in page.documents().iter() {
+                self.index_secondary(document, page_number)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn index_secondary(&mut self, doc: &T, page_number: u64) -> Result<(), CollectionError> {
+        for index in self.secondary_indexes.values_mut() {
+            if let Some(pages) = index.pages.as_mut() {
+                pages
+                    .entry((index.key)(doc)?)
+                    .or_default()
+                    .insert(page_number);
+            }
         }
+        for index in self.ordered_indexes.values_mut() {
+            index.insert(doc, page_number);
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self, doc: &T) -> Result<(), CollectionError> {
+        for validator in self.validators.iter() {
+            validator(doc).map_err(CollectionError::ValidationFailed)?;
+        }
+
+        Ok(())
+    }
+
+    fn prepare(&self, doc: &T) -> Result<T, CollectionError> {
+        let doc = match self.normalizer {
+            Some(normalize) => normalize(doc.clone()),
+            None => doc.clone(),
+        };
+
+        self.validate(&doc)?;
+
+        Ok(doc)
     }
 
     fn write_document_to_page(
@@ -57,207 +709,5238 @@ impl<T: Document> Collection<T> {
         collection_page.insert_document(&doc)?;
 
         self.collection_file.write_page(&collection_page)?;
-        self.id_to_page_map.insert(doc_id, 0);
+        if let Some(index) = self.id_to_page_map.get_mut() {
+            index.insert(doc_id, collection_page.get_page_number());
+        }
+        self.index_secondary(doc, collection_page.get_page_number())?;
         Ok(())
     }
 
     fn get_first_page_with_enough_space(
         &self,
         doc_size: u64,
+    ) -> Result<CollectionPage<T>, CollectionError> {
+        self.get_first_page_with_enough_space_from(doc_size, 0)
+    }
+
+    /// Like `get_first_page_with_enough_space`, only looking at pages from `first_page` on.
+    /// A page past the last one is created when none of them has room.
+    fn get_first_page_with_enough_space_from(
+        &self,
+        doc_size: u64,
+        first_page: u64,
     ) -> Result<CollectionPage<T>, CollectionError> {
         let number_of_pages = self.collection_file.number_of_pages();
 
         if number_of_pages == 0 {
-            return Ok(CollectionPage::<T>::new(0));
+            return Ok(self.collection_file.new_page(0));
         }
 
-        for i in 0..number_of_pages {
+        for i in first_page..number_of_pages {
             let collection_page_header = self.collection_file.read_page_header(i)?;
 
-            if collection_page_header.space_available() >= doc_size {
-                return Ok(self.collection_file.read_page(i)?);
+            if self.has_room(&collection_page_header, doc_size)? {
+                let mut page = self.collection_file.read_page(i)?;
+                // Empty pages, like the first one of a new file, get the slot directory too.
+                if self.collection_file.slot_directory() {
+                    page.enable_slot_directory();
+                }
+
+                return Ok(page);
             }
         }
 
-        return Ok(CollectionPage::<T>::new(number_of_pages));
+        return Ok(self.collection_file.new_page(number_of_pages));
     }
 
-    fn insert_one(&mut self, doc: &T) -> Result<(), CollectionError> {
-        let doc_id = doc.id();
-        let document_size = bincode::serialized_size(&doc)?;
+    /// Whether a new document fits in a page without filling it past the fill factor.
+    /// Empty pages take any document that fits.
+    fn has_room(
+        &self,
+        header: &CollectionPageHeader,
+        doc_size: u64,
+    ) -> Result<bool, CollectionError> {
+        let mut space_needed = self.collection_file.space_needed(doc_size)?;
 
-        if self.id_to_page_map.contains_key(&doc_id) {
-            return Err(CollectionError::DuplicateError);
+        if header.number_of_documents() > 0 {
+            space_needed +=
+                ((1.0 - self.fill_factor) * self.collection_file.page_data_size() as f64) as u64;
         }
 
-        if document_size > COLLECTION_PAGE_DATA_SIZE {
-            return Err(CollectionError::DocumentTooBig);
-        }
+        Ok(header.space_available() >= space_needed)
+    }
 
-        let mut page = self.get_first_page_with_enough_space(document_size)?;
+    pub fn insert_one(&mut self, doc: &T) -> Result<(), CollectionError> {
+        let doc = self.prepare(doc)?;
 
-        self.write_document_to_page(&doc, &mut page)?;
+        if self.pending_batch.is_some() {
+            self.check_insertable(&doc)?;
+            self.pending_batch.get_or_insert_with(Vec::new).push(doc);
+            return Ok(());
+        }
 
-        Ok(())
+        self.insert_prepared(&doc)
     }
 
-    fn find_by_id(&self, id: <T as HasId>::Id) -> Option<T> {
-        let page_number = self.id_to_page_map.get(&id)?;
+    /// Serialized size of a document, checked against the page size and `max_document_size`.
+    fn checked_document_size(&self, doc: &T) -> Result<u64, CollectionError> {
+        let document_size = self
+            .collection_file
+            .serialization_config()
+            .serialized_size(&doc)?;
+        self.check_document_size(document_size)?;
 
-        let page = self.collection_file.read_page(*page_number).ok()?;
-
-        page.find_document(id)
+        Ok(document_size)
     }
 
-    fn find_by(&self, filter: Filter<T>) -> Vec<T> {
-        let mut matching_docs: Vec<T> = vec![];
-        let mut page_number = 0;
-        while let Ok(page) = self.collection_file.read_page(page_number) {
-            for document in page.documents().iter() {
-                if filter(document) {
-                    matching_docs.push(document.to_owned());
-                }
-            }
-            page_number += 1;
+    fn check_document_size(&self, document_size: u64) -> Result<(), CollectionError> {
+        if self.collection_file.space_needed(document_size)? > self.collection_file.page_data_size()
+            || self
+                .max_document_size
+                .is_some_and(|max_document_size| document_size > max_document_size)
+        {
+            return Err(CollectionError::DocumentTooBig);
         }
 
-        matching_docs
+        Ok(())
     }
 
-    fn update_one(&mut self, doc_update: &T) -> Result<(), CollectionError> {
-        let doc_id = doc_update.id();
-        let page_number = self
-            .id_to_page_map
-            .get(&doc_id)
-            .ok_or(CollectionError::NotFoundError)?;
+    fn check_insertable(&self, doc: &T) -> Result<u64, CollectionError> {
+        if self.is_stored(doc.id())? {
+            return Err(CollectionError::DuplicateError);
+        }
 
-        let mut page = self.collection_file.read_page(*page_number)?;
+        self.checked_document_size(doc)
+    }
 
-        let update = page.update_document(&doc_update);
+    /// Whether a document with this id is indexed or pending, or with
+    /// `verify_unique_on_insert` stored on any page.
+    fn is_stored(&self, id: <T as HasId>::Id) -> Result<bool, CollectionError> {
+        if self.id_index()?.contains_key(&id) || self.pending_document(id).is_some() {
+            return Ok(true);
+        }
 
-        match update {
-            Ok(_) => Ok(()),
-            Err(CollectionPageError::NoFreeSpaceAvailable) => {
-                page.remove_document(doc_id)?;
-                self.insert_one(doc_update)?;
-                Ok(())
+        if self.verify_unique_on_insert {
+            for page in self.collection_file.non_empty_pages() {
+                if page?.find_document(id).is_some() {
+                    return Ok(true);
+                }
             }
-            Err(e) => Err(CollectionError::PageError(e)),
         }
+
+        Ok(false)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::document::HasId;
-    use serde_derive::{Deserialize, Serialize};
-    use tempfile::tempdir;
+    fn insert_prepared(&mut self, doc: &T) -> Result<(), CollectionError> {
+        let document_size = self.check_insertable(doc)?;
 
-    #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
-    struct MyDocument {
-        id: u64,
-        name: String,
-    }
+        let mut page = self.get_first_page_with_enough_space(document_size)?;
 
-    impl HasId for MyDocument {
-        type Id = u64;
+        self.write_document_to_page(&doc, &mut page)?;
+        self.append_page = page.get_page_number();
 
-        fn id(&self) -> u64 {
-            self.id
-        }
+        Ok(())
     }
 
-    #[test]
-    fn test_insert_one_find_one_by_id() {
-        let dir = tempdir().unwrap();
-        let binding = dir.into_path();
-        let dir_name = binding.to_str().unwrap();
-        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+    /// Inserts a document on page `page_number`, or on a new page right after the last one,
+    /// instead of the first page with room. The fill factor is not applied, only a page
+    /// without space for the document is rejected.
+    fn insert_into_page(&mut self, page_number: u64, doc: &T) -> Result<(), CollectionError> {
+        let doc = self.prepare(doc)?;
+        self.check_insertable(&doc)?;
 
-        let document: MyDocument = MyDocument {
-            id: 0,
-            name: String::from("test1"),
+        let mut page = if page_number == self.collection_file.number_of_pages() {
+            self.collection_file.new_page(page_number)
+        } else {
+            self.collection_file.read_page(page_number)?
         };
+        if self.collection_file.slot_directory() {
+            page.enable_slot_directory();
+        }
 
-        collection.insert_one(&document).unwrap();
+        self.write_document_to_page(&doc, &mut page)
+    }
 
-        let doc_from_collection = collection.find_by_id(0).unwrap();
+    /// Inserts a document already encoded with the collection's serialization config, for
+    /// example forwarded from a replica. Its size is checked on `bytes` instead of encoding
+    /// the document again, the bytes are only decoded to check the id and run the validators.
+    fn insert_serialized(
+        &mut self,
+        id: <T as HasId>::Id,
+        bytes: Vec<u8>,
+    ) -> Result<(), CollectionError> {
+        let document_size = bytes.len() as u64;
+        self.check_document_size(document_size)?;
 
-        assert_eq!(document, doc_from_collection);
-    }
+        let doc = self
+            .collection_file
+            .serialization_config()
+            .deserialize::<T>(&bytes)?;
+        if doc.id() != id {
+            return Err(CollectionError::IdMismatchError);
+        }
+        if self.is_stored(id)? {
+            return Err(CollectionError::DuplicateError);
+        }
+        self.validate(&doc)?;
 
-    #[test]
-    fn test_insert_find_all_collection() {
+        if let Some(pending) = self.pending_batch.as_mut() {
+            pending.push(doc);
+            return Ok(());
+        }
+
+        let mut page = self.get_first_page_with_enough_space(document_size)?;
+        self.write_document_to_page(&doc, &mut page)?;
+        self.append_page = page.get_page_number();
+
+        Ok(())
+    }
+
+    /// Inserts every document of `iter`, calling `progress` with the number of documents
+    /// processed so far every `progress_interval` documents. Returns how many were inserted.
+    fn import_iter<I: Iterator<Item = T>, F: FnMut(usize)>(
+        &mut self,
+        iter: I,
+        mut progress: F,
+    ) -> Result<usize, CollectionError> {
+        let progress_interval = self.import_options.progress_interval.max(1);
+        let mut inserted = 0;
+
+        for (index, document) in iter.enumerate() {
+            match self.insert_one(&document) {
+                Ok(_) => inserted += 1,
+                Err(CollectionError::DuplicateError)
+                    if self.import_options.on_duplicate == DuplicatePolicy::Skip => {}
+                Err(e) => return Err(e),
+            }
+
+            if (index + 1) % progress_interval == 0 {
+                progress(index + 1);
+            }
+        }
+
+        Ok(inserted)
+    }
+
+    /// Replaces every document of the collection by `docs`. The new documents are written to
+    /// `{name}.collection.tmp`, which is then renamed over the collection file, so the
+    /// collection holds either the old or the new documents, never a mix of both. Documents
+    /// of an open batch are discarded along with the old ones, the batch stays open.
+    fn replace_all(&mut self, docs: impl Iterator<Item = T>) -> Result<(), CollectionError> {
+        self.rewrite(docs, self.collection_file.page_size())
+    }
+
+    /// Rewrites the collection with pages of `new_page_size` bytes, swapped in the same way as
+    /// `replace_all`. Fails with `DocumentTooBig`, leaving the collection untouched, when a
+    /// document doesn't fit in the new pages. Documents of an open batch are written to the
+    /// new pages, which commits them.
+    fn resize_pages(&mut self, new_page_size: u64) -> Result<(), CollectionError> {
+        let mut documents = vec![];
+        self.find_by_each(
+            |_| true,
+            |document| {
+                documents.push(document);
+                true
+            },
+        )?;
+
+        self.rewrite(documents.into_iter(), new_page_size)
+    }
+
+    fn rewrite(
+        &mut self,
+        docs: impl Iterator<Item = T>,
+        page_size: u64,
+    ) -> Result<(), CollectionError> {
+        let name = self.collection_file.name().to_string();
+        let dir = self.collection_file.dir().to_string();
+        let path = format!("{}/{}.collection", dir, name);
+        let tmp_path = format!("{}.tmp", path);
+
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .read(true)
+            .truncate(true)
+            .open(&tmp_path)
+            .map_err(CollectionFileError::from)?;
+        let mut replacement = CollectionFile::from_storage(
+            &name,
+            &dir,
+            Box::new(file),
+            self.collection_file.serialization_config(),
+            Some(page_size),
+            true,
+        )?;
+        replacement.set_slot_directory(self.collection_file.slot_directory());
+        if self.collection_file.is_write_back() {
+            replacement.set_write_back()?;
+        }
+
+        let replacement = match self.write_replacement(replacement, docs) {
+            Ok(replacement) => replacement,
+            Err(e) => {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(e);
+            }
+        };
+
+        fs::rename(&tmp_path, &path).map_err(CollectionFileError::from)?;
+
+        // The file handle of the replacement follows the rename.
+        self.forget_cached(None);
+        self.id_to_page_map = OnceLock::from(index_collection_id_with_hasher(&replacement)?);
+        self.collection_file = replacement;
+        // `docs` replaced the pending documents too, see `replace_all` and `resize_pages`.
+        if let Some(pending_batch) = self.pending_batch.as_mut() {
+            pending_batch.clear();
+        }
+        self.build_secondary_indexes()?;
+
+        Ok(())
+    }
+
+    fn write_replacement(
+        &self,
+        mut replacement: CollectionFile<T>,
+        docs: impl Iterator<Item = T>,
+    ) -> Result<CollectionFile<T>, CollectionError> {
+        let mut ids = HashSet::new();
+        let mut page = replacement.new_page(0);
+
+        for doc in docs {
+            let doc = self.prepare(&doc)?;
+
+            if !ids.insert(doc.id()) {
+                return Err(CollectionError::DuplicateError);
+            }
+
+            let document_size = self.checked_document_size(&doc)?;
+            if replacement.space_needed(document_size)? > replacement.page_data_size() {
+                return Err(CollectionError::DocumentTooBig);
+            }
+
+            match page.insert_document(&doc) {
+                Ok(_) => {}
+                Err(CollectionPageError::NoFreeSpaceAvailable) => {
+                    replacement.write_page(&page)?;
+                    page = replacement.new_page(page.get_page_number() + 1);
+                    page.insert_document(&doc)?;
+                }
+                Err(e) => return Err(CollectionError::PageError(e)),
+            }
+        }
+
+        replacement.write_page(&page)?;
+        replacement.sync_data()?;
+
+        Ok(replacement)
+    }
+
+    /// Copies the collection file to `{dir}/{new_name}.collection` and opens the copy, which
+    /// gets a collection id of its own. Documents of a batch that isn't committed yet are not
+    /// part of the copy.
+    fn duplicate(&self, new_name: &str) -> Result<Collection<T>, CollectionError> {
+        let dir = self.collection_file.dir();
+
+        self.collection_file.sync_data()?;
+        let path = format!("{}/{}.collection", dir, new_name);
+        fs::copy(
+            format!("{}/{}.collection", dir, self.collection_file.name()),
+            &path,
+        )
+        .map_err(CollectionFileError::from)?;
+
+        // The copy gets its own collection id, pages of one are foreign to the other.
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(CollectionFileError::from)?;
+        CollectionFile::<T>::renew_collection_id(&file)?;
+        file.sync_data().map_err(CollectionFileError::from)?;
+
+        let mut builder = Collection::builder(new_name, dir)
+            .with_serialization_config(self.collection_file.serialization_config())
+            .with_page_size(self.collection_file.page_size());
+        if self.collection_file.slot_directory() {
+            builder = builder.with_slot_directory();
+        }
+
+        builder.build()
+    }
+
+    /// Writes the pages to `shards` collections named `{name}_shard_{i}` in `dir`, page `p`
+    /// going to shard `p % shards`. Each shard is a regular collection with the same
+    /// serialization config and page size, existing shards are replaced. Documents of a
+    /// batch that isn't committed yet are not exported, `shards` of 0 is taken as 1.
+    fn export_pages_as_shards(&self, dir: &str, shards: u64) -> Result<(), CollectionError> {
+        let shards = shards.max(1);
+        let mut shard_files = vec![];
+        for shard in 0..shards {
+            let name = shard_name(self.collection_file.name(), shard);
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .read(true)
+                .truncate(true)
+                .open(format!("{}/{}.collection", dir, name))
+                .map_err(CollectionFileError::from)?;
+            let mut shard_file = CollectionFile::from_storage(
+                &name,
+                dir,
+                Box::new(file),
+                self.collection_file.serialization_config(),
+                Some(self.collection_file.page_size()),
+                false,
+            )?;
+            shard_file.set_slot_directory(self.collection_file.slot_directory());
+            shard_files.push(shard_file);
+        }
+
+        for page_number in 0..self.collection_file.number_of_pages() {
+            let page = self.collection_file.read_page(page_number)?;
+            let shard_file = &mut shard_files[(page_number % shards) as usize];
+
+            let mut shard_page = shard_file.new_page(shard_file.number_of_pages());
+            for document in page.documents().iter() {
+                shard_page.insert_document(document)?;
+            }
+            shard_file.write_page(&shard_page)?;
+        }
+
+        for shard_file in shard_files.iter() {
+            shard_file.sync_data()?;
+        }
+
+        Ok(())
+    }
+
+    /// Inserts the documents of the shards written by `export_pages_as_shards` for the
+    /// collection `name`, returning how many were inserted.
+    fn import_shards(
+        &mut self,
+        dir: &str,
+        name: &str,
+        shards: u64,
+    ) -> Result<usize, CollectionError> {
+        let mut imported = 0;
+        for shard in 0..shards.max(1) {
+            let shard_collection = Collection::<T>::builder(&shard_name(name, shard), dir)
+                .with_serialization_config(self.collection_file.serialization_config())
+                .with_page_size(self.collection_file.page_size())
+                .build()?;
+
+            let documents = shard_collection.find_by(|_| true)?;
+            self.insert_many(&documents)?;
+            imported += documents.len();
+        }
+
+        Ok(imported)
+    }
+
+    /// Whether some writes only live in memory so far: the documents of an open batch or
+    /// the pages written in write-back mode and not flushed yet.
+    pub fn has_unwritten_changes(&self) -> bool {
+        self.pending_batch.is_some() || self.collection_file.has_unflushed_pages()
+    }
+
+    /// Writes the pages of a collection built `with_write_back` to its file, in one write.
+    fn flush(&self) -> Result<(), CollectionError> {
+        self.collection_file.flush()?;
+        Ok(())
+    }
+
+    /// Saves the in memory index to `{name}.index` without syncing the data pages. Any page
+    /// write afterwards removes the saved index, so it is never loaded out of date.
+    fn flush_index(&self) -> Result<(), CollectionError>
+    where
+        <T as HasId>::Id: Serialize,
+    {
+        save_index(&self.collection_file, self.id_index()?)?;
+        Ok(())
+    }
+
+    /// Writes the id to page index in the format of `write_index`, for use outside of the
+    /// collection, see `import_index`.
+    fn export_index<W: Write>(&self, w: W) -> Result<(), CollectionError>
+    where
+        <T as HasId>::Id: Serialize,
+    {
+        write_index::<T, IndexHasher, W>(self.id_index()?, w)?;
+        Ok(())
+    }
+
+    /// Writes `project` of every document matching `filter` to `w` as one line of NDJSON,
+    /// returning how many lines were written. Pages are read one at a time, so only the
+    /// page being scanned is held in memory.
+    fn export_filtered<W: Write>(
+        &self,
+        filter: impl Fn(&T) -> bool,
+        project: impl Fn(&T) -> serde_json::Value,
+        mut w: W,
+    ) -> Result<u64, CollectionError> {
+        let mut written = 0;
+        let mut export = |document: &T| -> Result<(), CollectionError> {
+            if filter(document) {
+                writeln!(w, "{}", project(document)).map_err(CollectionFileError::from)?;
+                written += 1;
+            }
+            Ok(())
+        };
+
+        for page in self.collection_file.non_empty_pages() {
+            for document in page?.documents().iter() {
+                export(document)?;
+            }
+        }
+
+        for document in self.pending_batch.iter().flatten() {
+            export(document)?;
+        }
+
+        Ok(written)
+    }
+
+    /// Writes the id to page index sorted by id to `{name}.sorted_index`, with memory
+    /// bounded by `memory_budget` entries however large the collection. Pending batch
+    /// documents aren't indexed.
+    fn build_sorted_index(
+        &self,
+        memory_budget: usize,
+    ) -> Result<SortedIndex<<T as HasId>::Id>, CollectionError>
+    where
+        <T as HasId>::Id: Ord + Serialize + DeserializeOwned,
+    {
+        Ok(build_sorted_index(&self.collection_file, memory_budget)?)
+    }
+
+    /// Reads an index written by `export_index`.
+    fn import_index<R: Read>(r: R) -> Result<IdToPageMap<T, IndexHasher>, CollectionError>
+    where
+        <T as HasId>::Id: DeserializeOwned,
+    {
+        Ok(read_index::<T, IndexHasher, R>(r)?)
+    }
+
+    /// Starts buffering inserts in memory until `commit_batch` is called.
+    /// Buffered documents are visible to `find_by_id` and `find_by` before the commit.
+    pub fn begin_batch(&mut self) {
+        self.pending_batch.get_or_insert_with(Vec::new);
+    }
+
+    pub fn commit_batch(&mut self) -> Result<(), CollectionError> {
+        let pending = self.pending_batch.take().unwrap_or_default();
+
+        self.insert_many_prepared(&pending)
+    }
+
+    /// Makes room in the index for `additional` more documents ahead of a bulk load, so it
+    /// doesn't rehash as it grows. Does nothing while a lazy index isn't built yet.
+    fn reserve(&mut self, additional: usize) {
+        if let Some(index) = self.id_to_page_map.get_mut() {
+            index.reserve(additional);
+        }
+    }
+
+    /// Inserts several documents, serializing and writing each page they land on only once.
+    /// Every document is validated before anything is written.
+    pub fn insert_many(&mut self, docs: &[T]) -> Result<(), CollectionError> {
+        let docs = docs
+            .iter()
+            .map(|doc| self.prepare(doc))
+            .collect::<Result<Vec<T>, CollectionError>>()?;
+
+        if self.pending_batch.is_some() {
+            for doc in docs {
+                self.check_insertable(&doc)?;
+                self.pending_batch.get_or_insert_with(Vec::new).push(doc);
+            }
+            return Ok(());
+        }
+
+        self.insert_many_prepared(&docs)
+    }
+
+    fn insert_many_prepared(&mut self, docs: &[T]) -> Result<(), CollectionError> {
+        let mut ids = HashSet::new();
+        for doc in docs.iter() {
+            self.check_insertable(doc)?;
+
+            if !ids.insert(doc.id()) {
+                return Err(CollectionError::DuplicateError);
+            }
+        }
+
+        let mut page: Option<CollectionPage<T>> = None;
+
+        for doc in docs.iter() {
+            let document_size = self.checked_document_size(doc)?;
+
+            if let Some(current_page) = page.as_mut() {
+                if self.has_room(current_page.header(), document_size)? {
+                    match current_page.insert_document(doc) {
+                        Ok(_) => continue,
+                        Err(CollectionPageError::NoFreeSpaceAvailable) => {}
+                        Err(e) => return Err(CollectionError::PageError(e)),
+                    }
+                }
+
+                self.write_page_and_index(current_page)?;
+            }
+
+            let mut next_page = self.get_first_page_with_enough_space(document_size)?;
+            next_page.insert_document(doc)?;
+            page = Some(next_page);
+        }
+
+        if let Some(current_page) = page.as_ref() {
+            self.write_page_and_index(current_page)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_page_and_index(&mut self, page: &CollectionPage<T>) -> Result<(), CollectionError> {
+        self.collection_file.write_page(page)?;
+        self.append_page = page.get_page_number();
+
+        for document in page.documents().iter() {
+            if let Some(index) = self.id_to_page_map.get_mut() {
+                index.insert(document.id(), page.get_page_number());
+            }
+            self.index_secondary(document, page.get_page_number())?;
+        }
+
+        Ok(())
+    }
+
+    /// Page the last insert wrote to, 0 before any insert since the collection was opened.
+    fn current_append_page(&self) -> u64 {
+        self.append_page
+    }
+
+    /// Reads pages into the page cache, see `with_page_cache`.
+    fn warm_cache(&self, pages: impl IntoIterator<Item = u64>) -> Result<(), CollectionError> {
+        Ok(self.collection_file.warm_cache(pages)?)
+    }
+
+    fn io_stats(&self) -> IoStats {
+        self.collection_file.io_stats()
+    }
+
+    fn pending_document(&self, id: <T as HasId>::Id) -> Option<usize> {
+        self.pending_batch
+            .as_ref()?
+            .iter()
+            .position(|document| document.id() == id)
+    }
+
+    /// The document with the given id, `None` as well when it couldn't be read or the index
+    /// is out of date, see `try_find_by_id` to tell these cases apart.
+    pub fn find_by_id(&self, id: <T as HasId>::Id) -> Option<T> {
+        self.try_find_by_id(id).ok().flatten()
+    }
+
+    /// Like `find_by_id`, with read errors returned. When the indexed page doesn't hold the
+    /// document, the collection's `IndexMissPolicy` decides between returning `None`,
+    /// scanning every page for it and failing with `StaleIndexError`.
+    pub fn try_find_by_id(&self, id: <T as HasId>::Id) -> Result<Option<T>, CollectionError> {
+        if let Some(index) = self.pending_document(id) {
+            return Ok(self
+                .pending_batch
+                .as_ref()
+                .and_then(|batch| batch.get(index).cloned()));
+        }
+
+        if let Some(document_cache) = self.document_cache.as_ref() {
+            if let Some(document) = document_cache.documents.lock().unwrap().get(&id) {
+                return Ok(Some(document.clone()));
+            }
+        }
+
+        let page_number = match self.id_index()?.get(&id) {
+            Some(page_number) => *page_number,
+            None => return Ok(None),
+        };
+
+        if page_number < self.collection_file.number_of_pages() {
+            let page = self.collection_file.read_page(page_number)?;
+            if let Some(document) = page.find_document(id) {
+                self.cache_document(&document);
+                return Ok(Some(document));
+            }
+        }
+
+        match self.index_miss_policy {
+            IndexMissPolicy::ReturnNone => Ok(None),
+            IndexMissPolicy::Rescan => {
+                for page in self.collection_file.non_empty_pages() {
+                    if let Some(document) = page?.find_document(id) {
+                        return Ok(Some(document));
+                    }
+                }
+
+                Ok(None)
+            }
+            IndexMissPolicy::Error => Err(CollectionError::StaleIndexError),
+        }
+    }
+
+    fn cache_document(&self, document: &T) {
+        if let Some(document_cache) = self.document_cache.as_ref() {
+            let mut documents = document_cache.documents.lock().unwrap();
+            if documents.len() >= document_cache.capacity {
+                if let Some(evicted) = documents.keys().next().copied() {
+                    documents.remove(&evicted);
+                }
+            }
+            if document_cache.capacity > 0 {
+                documents.insert(document.id(), document.clone());
+            }
+        }
+    }
+
+    /// Drops the cached copy of a document about to change, or of every document with `None`.
+    fn forget_cached(&self, id: Option<<T as HasId>::Id>) {
+        if let Some(document_cache) = self.document_cache.as_ref() {
+            let mut documents = document_cache.documents.lock().unwrap();
+            match id {
+                Some(id) => {
+                    documents.remove(&id);
+                }
+                None => documents.clear(),
+            }
+        }
+    }
+
+    /// Like `find_by_id`, but when the indexed page doesn't hold the document the pages are
+    /// rescanned and the index entry is moved to the right page, or removed if it is gone.
+    fn find_by_id_checked(
+        &mut self,
+        id: <T as HasId>::Id,
+    ) -> Result<CheckedFind<T>, CollectionError> {
+        if let Some(index) = self.pending_document(id) {
+            return Ok(CheckedFind {
+                document: self
+                    .pending_batch
+                    .as_ref()
+                    .map(|batch| batch[index].clone()),
+                healed: false,
+            });
+        }
+
+        let indexed_page_number = match self.id_index()?.get(&id) {
+            Some(page_number) => *page_number,
+            None => {
+                return Ok(CheckedFind {
+                    document: None,
+                    healed: false,
+                })
+            }
+        };
+
+        if indexed_page_number < self.collection_file.number_of_pages() {
+            let page = self.collection_file.read_page(indexed_page_number)?;
+
+            if let Some(document) = page.find_document(id) {
+                return Ok(CheckedFind {
+                    document: Some(document),
+                    healed: false,
+                });
+            }
+        }
+
+        if let Some(index) = self.id_to_page_map.get_mut() {
+            index.remove(&id);
+        }
+
+        for page_number in 0..self.collection_file.number_of_pages() {
+            let page = self.collection_file.read_page(page_number)?;
+
+            if let Some(document) = page.find_document(id) {
+                if let Some(index) = self.id_to_page_map.get_mut() {
+                    index.insert(id, page_number);
+                }
+
+                return Ok(CheckedFind {
+                    document: Some(document),
+                    healed: true,
+                });
+            }
+        }
+
+        Ok(CheckedFind {
+            document: None,
+            healed: true,
+        })
+    }
+
+    /// JSON Schema of the document type, describing the shape of the stored documents.
+    #[cfg(feature = "json-schema")]
+    fn schema_json(&self) -> serde_json::Value
+    where
+        T: schemars::JsonSchema,
+    {
+        schemars::schema_for!(T).to_value()
+    }
+
+    /// Inserts the document built by `f` from a new random v4 UUID, returning the UUID.
+    #[cfg(feature = "uuid")]
+    fn insert_with_new_uuid<F: FnOnce(uuid::Uuid) -> T>(
+        &mut self,
+        f: F,
+    ) -> Result<uuid::Uuid, CollectionError>
+    where
+        T: HasId<Id = uuid::Uuid>,
+    {
+        let id = uuid::Uuid::new_v4();
+        let document = f(id);
+
+        if document.id() != id {
+            return Err(CollectionError::IdMismatchError);
+        }
+
+        self.insert_one(&document)?;
+
+        Ok(id)
+    }
+
+    /// Returns the document with the given id, inserting the one built by `f` if absent.
+    fn get_or_insert_with<F: FnOnce() -> T>(
+        &mut self,
+        id: <T as HasId>::Id,
+        f: F,
+    ) -> Result<T, CollectionError> {
+        if let Some(document) = self.find_by_id(id) {
+            return Ok(document);
+        }
+
+        let document = f();
+
+        if document.id() != id {
+            return Err(CollectionError::IdMismatchError);
+        }
+
+        self.insert_one(&document)?;
+
+        Ok(document)
+    }
+
+    /// The documents matching `filter`, pending batch documents last. Fails with
+    /// `ScanBudgetExceededError` as soon as the documents collected so far serialize to more
+    /// than the budget set with `with_scan_budget`.
+    pub fn find_by(&self, filter: Filter<T>) -> Result<Vec<T>, CollectionError> {
+        let mut matching_docs: Vec<T> = vec![];
+        let mut scanned_bytes = 0;
+        let mut collect = |document: &T| -> Result<(), CollectionError> {
+            if !filter(document) {
+                return Ok(());
+            }
+
+            if let Some(scan_budget) = self.scan_budget {
+                scanned_bytes += self
+                    .collection_file
+                    .serialization_config()
+                    .serialized_size(document)?;
+                if scanned_bytes > scan_budget {
+                    return Err(CollectionError::ScanBudgetExceededError);
+                }
+            }
+
+            matching_docs.push(document.to_owned());
+            Ok(())
+        };
+
+        for page in self.collection_file.non_empty_pages() {
+            let page = page?;
+            for document in page.documents().iter() {
+                collect(document)?;
+            }
+        }
+
+        for document in self.pending_batch.iter().flatten() {
+            collect(document)?;
+        }
+
+        Ok(matching_docs)
+    }
+
+    /// Pairs every document with the document of `other` whose id `key` extracts from it,
+    /// `None` when `other` has no such document. Each page of `other` holding a referenced
+    /// document is read once.
+    fn join_by<U: Document>(
+        &self,
+        other: &Collection<U>,
+        key: fn(&T) -> <U as HasId>::Id,
+    ) -> Result<Vec<(T, Option<U>)>, CollectionError> {
+        let documents = self.find_by(|_| true)?;
+        let other_index = other.id_index()?;
+
+        let mut ids_by_page: BTreeMap<u64, HashSet<<U as HasId>::Id>> = BTreeMap::new();
+        let mut joined: HashMap<<U as HasId>::Id, U> = HashMap::new();
+        for document in documents.iter() {
+            let id = key(document);
+            if let Some(index) = other.pending_document(id) {
+                if let Some(pending) = other.pending_batch.as_ref() {
+                    joined.insert(id, pending[index].clone());
+                }
+            } else if let Some(page_number) = other_index.get(&id) {
+                ids_by_page.entry(*page_number).or_default().insert(id);
+            }
+        }
+
+        for (page_number, ids) in ids_by_page {
+            if page_number >= other.collection_file.number_of_pages() {
+                continue;
+            }
+            let page = other.collection_file.read_page(page_number)?;
+            for id in ids {
+                if let Some(document) = page.find_document(id) {
+                    joined.insert(id, document);
+                }
+            }
+        }
+
+        Ok(documents
+            .into_iter()
+            .map(|document| {
+                let other_document = joined.get(&key(&document)).cloned();
+                (document, other_document)
+            })
+            .collect())
+    }
+
+    /// Makes `find_all_ordered` sort by `key`, in descending order when `descending` is set,
+    /// instead of by id. Documents with equal keys stay sorted by id.
+    fn set_default_order<K: Ord>(
+        &mut self,
+        key: impl Fn(&T) -> K + Send + Sync + 'static,
+        descending: bool,
+    ) {
+        self.default_order = Some(Box::new(move |a, b| {
+            let order = key(a).cmp(&key(b));
+            if descending {
+                order.reverse()
+            } else {
+                order
+            }
+        }));
+    }
+
+    /// Every document sorted by id, or by the order set with `set_default_order`. The order
+    /// doesn't depend on where documents are stored and so survives removals, relocations
+    /// and reopens.
+    fn find_all_ordered(&self) -> Result<Vec<T>, CollectionError>
+    where
+        <T as HasId>::Id: Ord,
+    {
+        let mut documents = self.find_by(|_| true)?;
+        documents.sort_by_key(|document| document.id());
+        if let Some(default_order) = self.default_order.as_ref() {
+            documents.sort_by(|a, b| default_order(a, b));
+        }
+
+        Ok(documents)
+    }
+
+    /// Hands every document matching `filter` to `sink` as it is found, pending batch
+    /// documents last. The scan stops, without reading further pages, once `sink` returns false.
+    fn find_by_each<F: FnMut(T) -> bool>(
+        &self,
+        filter: impl Fn(&T) -> bool,
+        mut sink: F,
+    ) -> Result<(), CollectionError> {
+        for page in self.collection_file.non_empty_pages() {
+            for document in page?.documents().iter() {
+                if filter(document) && !sink(document.clone()) {
+                    return Ok(());
+                }
+            }
+        }
+
+        for document in self.pending_batch.iter().flatten() {
+            if filter(document) && !sink(document.clone()) {
+                return Ok(());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Every document in ascending id order. Each page is sorted on its own, then the pages
+    /// are merged, so no sort over the whole collection is needed.
+    fn iter_sorted(&self) -> Result<impl Iterator<Item = T> + '_, CollectionError>
+    where
+        <T as HasId>::Id: Ord,
+    {
+        let mut runs = vec![];
+        for page in self.collection_file.non_empty_pages() {
+            let mut documents = page?.documents().clone();
+            documents.sort_by_key(|document| document.id());
+            runs.push(documents);
+        }
+
+        let mut pending = self.pending_batch.clone().unwrap_or_default();
+        pending.sort_by_key(|document| document.id());
+        runs.push(pending);
+
+        Ok(SortedMerge::new(runs))
+    }
+
+    /// Reads every document as `U`, typically a struct with a subset of the fields of `T`.
+    /// Only possible when the collection uses a self-describing codec such as JSON.
+    fn view_as<U: Document>(&self) -> Result<Vec<U>, CollectionError> {
+        let serialization_config = self.collection_file.serialization_config();
+        if !serialization_config.is_self_describing() {
+            return Err(CollectionError::CodecNotSelfDescribingError);
+        }
+
+        let mut documents = vec![];
+        for page_number in 0..self.collection_file.number_of_pages() {
+            let page = self.collection_file.read_page_as::<U>(page_number)?;
+            documents.extend(page.documents().iter().cloned());
+        }
+
+        for document in self.pending_batch.iter().flatten() {
+            let encoded = serialization_config.serialize(document)?;
+            documents.push(serialization_config.deserialize::<U>(&encoded)?);
+        }
+
+        Ok(documents)
+    }
+
+    /// Ids of the documents stored on a page, without decoding the other fields of the
+    /// documents. Requires a self-describing codec and documents storing their id as `id`.
+    fn peek_ids_on_page(&self, page_number: u64) -> Result<Vec<<T as HasId>::Id>, CollectionError>
+    where
+        <T as HasId>::Id: DeserializeOwned,
+    {
+        if !self
+            .collection_file
+            .serialization_config()
+            .is_self_describing()
+        {
+            return Err(CollectionError::CodecNotSelfDescribingError);
+        }
+
+        Ok(self.collection_file.read_page_ids(page_number)?)
+    }
+
+    /// Documents whose key `index_name` equals `key`. Only the pages listed by the index are
+    /// read, a key registered without an index falls back to scanning every page.
+    fn find_by_using_index<K: Serialize>(
+        &self,
+        index_name: &str,
+        key: K,
+    ) -> Result<Vec<T>, CollectionError> {
+        let index = self
+            .secondary_indexes
+            .get(index_name)
+            .ok_or(CollectionError::NotFoundError)?;
+        let encoded_key = bincode::serialize(&key)?;
+
+        let page_numbers: Vec<u64> = match index.pages.as_ref() {
+            Some(pages) => pages
+                .get(&encoded_key)
+                .map(|pages| pages.iter().copied().collect())
+                .unwrap_or_default(),
+            None => (0..self.collection_file.number_of_pages()).collect(),
+        };
+
+        let mut matching_docs = vec![];
+        for page_number in page_numbers {
+            let page = self.collection_file.read_page(page_number)?;
+            for document in page.documents().iter() {
+                if (index.key)(document)? == encoded_key {
+                    matching_docs.push(document.clone());
+                }
+            }
+        }
+
+        for document in self.pending_batch.iter().flatten() {
+            if (index.key)(document)? == encoded_key {
+                matching_docs.push(document.clone());
+            }
+        }
+
+        Ok(matching_docs)
+    }
+
+    /// Documents whose key `index_name` falls in `range`, in page order. Only the pages the
+    /// ordered index lists for keys in the range are read. `K` must be the key type the
+    /// index was built with, otherwise the index isn't found.
+    fn range<K: Ord + 'static, R: RangeBounds<K>>(
+        &self,
+        index_name: &str,
+        range: R,
+    ) -> Result<Vec<T>, CollectionError>
+    where
+        T: 'static,
+    {
+        let index = self
+            .ordered_indexes
+            .get(index_name)
+            .and_then(|index| index.as_any().downcast_ref::<BTreeIndex<T, K>>())
+            .ok_or(CollectionError::NotFoundError)?;
+
+        let page_numbers: BTreeSet<u64> = index
+            .pages
+            .range((range.start_bound(), range.end_bound()))
+            .flat_map(|(_, pages)| pages.iter().copied())
+            .collect();
+
+        let mut matching_docs = vec![];
+        for page_number in page_numbers {
+            let page = self.collection_file.read_page(page_number)?;
+            for document in page.documents().iter() {
+                if range.contains(&(index.key)(document)) {
+                    matching_docs.push(document.clone());
+                }
+            }
+        }
+
+        for document in self.pending_batch.iter().flatten() {
+            if range.contains(&(index.key)(document)) {
+                matching_docs.push(document.clone());
+            }
+        }
+
+        Ok(matching_docs)
+    }
+
+    /// Deletes the documents whose key `index_name` equals `key`, returning how many were
+    /// deleted. Only the pages listed by the index are read and each of them is written once,
+    /// a key registered without an index falls back to scanning every page.
+    fn delete_by_index<K: Serialize>(
+        &mut self,
+        index_name: &str,
+        key: K,
+    ) -> Result<usize, CollectionError> {
+        let index = self
+            .secondary_indexes
+            .get(index_name)
+            .ok_or(CollectionError::NotFoundError)?;
+        let encoded_key = bincode::serialize(&key)?;
+
+        let page_numbers: Vec<u64> = match index.pages.as_ref() {
+            Some(pages) => pages
+                .get(&encoded_key)
+                .map(|pages| pages.iter().copied().collect())
+                .unwrap_or_default(),
+            None => (0..self.collection_file.number_of_pages()).collect(),
+        };
+
+        let mut removed = 0;
+        for page_number in page_numbers {
+            let mut page = self.collection_file.read_page(page_number)?;
+            let mut removed_ids = vec![];
+            for document in page.documents().iter() {
+                if (index.key)(document)? == encoded_key {
+                    removed_ids.push(document.id());
+                }
+            }
+
+            if removed_ids.is_empty() {
+                continue;
+            }
+
+            for id in removed_ids.iter() {
+                page.remove_document(*id)?;
+                self.forget_cached(Some(*id));
+            }
+            self.collection_file.write_page(&page)?;
+            if let Some(id_index) = self.id_to_page_map.get_mut() {
+                for id in removed_ids.iter() {
+                    id_index.remove(id);
+                }
+            }
+            removed += removed_ids.len();
+        }
+
+        if let Some(pending) = self.pending_batch.as_mut() {
+            let pending_len = pending.len();
+            let mut kept = Vec::with_capacity(pending_len);
+            for document in pending.drain(..) {
+                if (index.key)(&document)? != encoded_key {
+                    kept.push(document);
+                }
+            }
+            removed += pending_len - kept.len();
+            *pending = kept;
+        }
+
+        // No document has the key anymore. The pages listed for other keys may now hold
+        // fewer of them, which indexes allow.
+        if let Some(pages) = self
+            .secondary_indexes
+            .get_mut(index_name)
+            .and_then(|index| index.pages.as_mut())
+        {
+            pages.remove(&encoded_key);
+        }
+
+        Ok(removed)
+    }
+
+    /// Documents grouped by `key`, pending batch documents included.
+    fn group_by<K: Eq + Hash, F: Fn(&T) -> K>(
+        &self,
+        key: F,
+    ) -> Result<HashMap<K, Vec<T>>, CollectionError> {
+        let mut groups: HashMap<K, Vec<T>> = HashMap::new();
+        for page in self.collection_file.non_empty_pages() {
+            for document in page?.documents().iter() {
+                groups
+                    .entry(key(document))
+                    .or_default()
+                    .push(document.to_owned());
+            }
+        }
+        for document in self.pending_batch.iter().flatten() {
+            groups
+                .entry(key(document))
+                .or_default()
+                .push(document.to_owned());
+        }
+
+        Ok(groups)
+    }
+
+    /// Like `group_by` but only keeps a count per key, memory is bounded by the number of keys.
+    fn group_counts<K: Eq + Hash, F: Fn(&T) -> K>(
+        &self,
+        key: F,
+    ) -> Result<HashMap<K, usize>, CollectionError> {
+        let mut counts: HashMap<K, usize> = HashMap::new();
+        for page in self.collection_file.non_empty_pages() {
+            for document in page?.documents().iter() {
+                *counts.entry(key(document)).or_default() += 1;
+            }
+        }
+        for document in self.pending_batch.iter().flatten() {
+            *counts.entry(key(document)).or_default() += 1;
+        }
+
+        Ok(counts)
+    }
+
+    pub fn update_one(&mut self, doc_update: &T) -> Result<(), CollectionError> {
+        let doc_update = self.prepare(doc_update)?;
+        let document_size = self.checked_document_size(&doc_update)?;
+
+        let doc_id = doc_update.id();
+        self.forget_cached(Some(doc_id));
+
+        if let (Some(index), Some(pending)) =
+            (self.pending_document(doc_id), self.pending_batch.as_mut())
+        {
+            pending[index] = doc_update;
+            return Ok(());
+        }
+
+        let page_number = *self
+            .id_index()?
+            .get(&doc_id)
+            .ok_or(CollectionError::NotFoundError)?;
+
+        let mut page = self.collection_file.read_page(page_number)?;
+        let previous = page.find_document(doc_id);
+
+        let update = page.update_document(&doc_update);
+
+        match update {
+            Ok(_) => {
+                self.collection_file.write_page(&page)?;
+                self.index_secondary(&doc_update, page.get_page_number())?;
+            }
+            Err(CollectionPageError::NoFreeSpaceAvailable) => {
+                // The new location is written (and synced when durable) before the old copy
+                // is removed: a crash in between leaves the document on two pages, never none.
+                // The new copy goes to a later page, where the index built on reopen, which
+                // keeps the last page holding an id, finds it over the stale one.
+                let mut new_page =
+                    self.get_first_page_with_enough_space_from(document_size, page_number + 1)?;
+                self.write_document_to_page(&doc_update, &mut new_page)?;
+                self.sync_if_durable()?;
+
+                page.remove_document(doc_id)?;
+                self.collection_file.write_page(&page)?;
+                self.sync_if_durable()?;
+            }
+            Err(e) => return Err(CollectionError::PageError(e)),
+        }
+
+        if let (Some(history), Some(previous)) = (self.history.as_mut(), previous) {
+            history.record(&previous)?;
+        }
+
+        Ok(())
+    }
+
+    /// Adds `delta` to the counter `field` points to in a document and writes it back,
+    /// returning the new value. Use `SyncCollection::increment_field` to do it under the
+    /// write lock from several threads.
+    pub fn increment_field(
+        &mut self,
+        id: <T as HasId>::Id,
+        delta: i64,
+        field: impl Fn(&mut T) -> &mut i64,
+    ) -> Result<i64, CollectionError> {
+        let mut document = self.find_by_id(id).ok_or(CollectionError::NotFoundError)?;
+
+        let value = field(&mut document);
+        *value += delta;
+        let value = *value;
+
+        self.update_one(&document)?;
+
+        Ok(value)
+    }
+
+    /// Updates the document only if `predicate` holds for its current version, returning
+    /// whether it was updated.
+    fn update_if(
+        &mut self,
+        doc_update: &T,
+        predicate: impl Fn(&T) -> bool,
+    ) -> Result<bool, CollectionError> {
+        let current = self
+            .find_by_id(doc_update.id())
+            .ok_or(CollectionError::NotFoundError)?;
+
+        if !predicate(&current) {
+            return Ok(false);
+        }
+
+        self.update_one(doc_update)?;
+
+        Ok(true)
+    }
+
+    /// Past versions of a document, oldest first. Requires versioning to be enabled.
+    fn versions(&self, id: <T as HasId>::Id) -> Result<Vec<T>, CollectionError> {
+        let history = self
+            .history
+            .as_ref()
+            .ok_or(CollectionError::VersioningDisabled)?;
+
+        Ok(history.versions(id)?)
+    }
+
+    /// Keeps only the `keep` most recent past versions of a document, returning
+    /// how many were removed. The current version isn't counted.
+    fn prune_versions(
+        &mut self,
+        id: <T as HasId>::Id,
+        keep: usize,
+    ) -> Result<usize, CollectionError> {
+        let history = self
+            .history
+            .as_mut()
+            .ok_or(CollectionError::VersioningDisabled)?;
+
+        Ok(history.prune(id, keep)?)
+    }
+
+    pub fn delete_one(&mut self, id: <T as HasId>::Id) -> Result<T, CollectionError> {
+        self.forget_cached(Some(id));
+
+        if let (Some(index), Some(pending)) =
+            (self.pending_document(id), self.pending_batch.as_mut())
+        {
+            return Ok(pending.remove(index));
+        }
+
+        let page_number = *self
+            .id_index()?
+            .get(&id)
+            .ok_or(CollectionError::NotFoundError)?;
+
+        let mut page = self.collection_file.read_page(page_number)?;
+        let document = page.remove_document(id)?;
+
+        self.collection_file.write_page(&page)?;
+        if let Some(index) = self.id_to_page_map.get_mut() {
+            index.remove(&id);
+        }
+
+        Ok(document)
+    }
+
+    /// Deletes every document for which `predicate` returns false, writing each affected page
+    /// once, and returns how many were deleted.
+    fn retain(&mut self, predicate: impl Fn(&T) -> bool) -> Result<usize, CollectionError> {
+        let mut removed = 0;
+
+        for page_number in 0..self.collection_file.number_of_pages() {
+            let mut page = self.collection_file.read_page(page_number)?;
+            let mut removed_ids = vec![];
+            page.documents_mut().retain(|document| {
+                let keep = predicate(document);
+                if !keep {
+                    removed_ids.push(document.id());
+                }
+                keep
+            });
+
+            if removed_ids.is_empty() {
+                continue;
+            }
+
+            page.defragment()?;
+            self.collection_file.write_page(&page)?;
+            for id in removed_ids.iter() {
+                self.forget_cached(Some(*id));
+            }
+            if let Some(index) = self.id_to_page_map.get_mut() {
+                for id in removed_ids.iter() {
+                    index.remove(id);
+                }
+            }
+            removed += removed_ids.len();
+        }
+
+        if let Some(pending) = self.pending_batch.as_mut() {
+            let pending_len = pending.len();
+            pending.retain(|document| predicate(document));
+            removed += pending_len - pending.len();
+        }
+
+        Ok(removed)
+    }
+
+    /// Stores a small value under `key` in the `{name}.meta` sidecar, replacing any previous
+    /// value. Meant for application state such as a sync cursor.
+    fn set_meta(&mut self, key: &str, value: &[u8]) -> Result<(), CollectionError> {
+        Ok(self.meta.set(key, value)?)
+    }
+
+    fn get_meta(&self, key: &str) -> Option<Vec<u8>> {
+        self.meta.get(key)
+    }
+
+    /// Reserves `n` consecutive ids from the `{name}.id_sequence` sidecar, for writers that
+    /// assign ids themselves. Ids of documents inserted with other ids are not skipped.
+    fn allocate_ids(&mut self, n: u64) -> Result<Range<u64>, CollectionError>
+    where
+        T: HasId<Id = u64>,
+    {
+        Ok(self.id_sequence.allocate(n)?)
+    }
+
+    /// Attaches a binary blob to a stored document, kept in overflow pages of a sidecar
+    /// file so it doesn't take space in the document pages.
+    fn put_blob(&mut self, id: <T as HasId>::Id, blob: &[u8]) -> Result<(), CollectionError>
+    where
+        <T as HasId>::Id: Serialize,
+    {
+        if !self.id_index()?.contains_key(&id) {
+            return Err(CollectionError::NotFoundError);
+        }
+
+        let blob_store = match self.blob_store.as_mut() {
+            Some(blob_store) => blob_store,
+            None => self.blob_store.insert(BlobStore::open(
+                self.collection_file.name(),
+                self.collection_file.dir(),
+            )?),
+        };
+
+        blob_store.put(bincode::serialize(&id)?, blob)?;
+
+        Ok(())
+    }
+
+    fn get_blob(&self, id: <T as HasId>::Id) -> Option<Vec<u8>>
+    where
+        <T as HasId>::Id: Serialize,
+    {
+        let key = bincode::serialize(&id).ok()?;
+
+        self.blob_store.as_ref()?.get(&key).ok()?
+    }
+
+    /// Decodes every document of every page, reporting the position of those that can't be
+    /// decoded instead of stopping at the first one.
+    fn scrub(&self) -> Result<ScrubReport, CollectionError> {
+        let serialization_config = self.collection_file.serialization_config();
+        let mut report = ScrubReport::default();
+
+        for page_number in 0..self.collection_file.number_of_pages() {
+            let encoded = self.collection_file.read_page_bytes(page_number)?;
+            let (documents_read, failure) =
+                scrub_page::<T>(&serialization_config, page_number, &encoded);
+
+            report.pages_scanned += 1;
+            report.documents_scanned += documents_read;
+            report.failures.extend(failure);
+        }
+
+        Ok(report)
+    }
+
+    /// Ids stored on more than one page, with the pages holding them.
+    fn find_duplicate_ids(&self) -> Result<Vec<DuplicateId<T>>, CollectionError> {
+        let mut pages_by_id: HashMap<<T as HasId>::Id, Vec<u64>> = HashMap::new();
+        let mut ids = vec![];
+
+        for page_number in 0..self.collection_file.number_of_pages() {
+            let page = self.collection_file.read_page(page_number)?;
+
+            for document in page.documents().iter() {
+                let pages = pages_by_id.entry(document.id()).or_default();
+                if pages.is_empty() {
+                    ids.push(document.id());
+                }
+                if pages.last() != Some(&page_number) {
+                    pages.push(page_number);
+                }
+            }
+        }
+
+        Ok(ids
+            .into_iter()
+            .filter_map(|id| match pages_by_id.remove(&id) {
+                Some(pages) if pages.len() > 1 => Some((id, pages)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Compares the stored documents of two collections. Ids come from the indexes, only pages
+    /// holding ids present on both sides are read, each at most once. Documents are compared
+    /// by a hash of their bincode encoding, pending batches are left out.
+    fn diff(&self, other: &Collection<T>) -> Result<CollectionDiff<T>, CollectionError> {
+        let self_index = self.id_index()?;
+        let other_index = other.id_index()?;
+        let digest = |document: &T| -> Result<u64, CollectionError> {
+            let mut hasher = FnvHasher::default();
+            hasher.write(&bincode::serialize(document)?);
+            Ok(hasher.finish())
+        };
+
+        let mut only_in_self = vec![];
+        let mut common_ids: BTreeMap<u64, Vec<<T as HasId>::Id>> = BTreeMap::new();
+        let mut other_common_ids: BTreeMap<u64, Vec<<T as HasId>::Id>> = BTreeMap::new();
+        for (id, page_number) in self_index.iter() {
+            match other_index.get(id) {
+                Some(other_page_number) => {
+                    common_ids.entry(*page_number).or_default().push(*id);
+                    other_common_ids
+                        .entry(*other_page_number)
+                        .or_default()
+                        .push(*id);
+                }
+                None => only_in_self.push(*id),
+            }
+        }
+        let only_in_other = other_index
+            .keys()
+            .filter(|id| !self_index.contains_key(id))
+            .copied()
+            .collect();
+
+        let mut other_digests = HashMap::new();
+        for (page_number, ids) in other_common_ids {
+            if page_number >= other.collection_file.number_of_pages() {
+                continue;
+            }
+            let page = other.collection_file.read_page(page_number)?;
+            for id in ids {
+                if let Some(document) = page.find_document(id) {
+                    other_digests.insert(id, digest(&document)?);
+                }
+            }
+        }
+
+        let mut changed = vec![];
+        for (page_number, ids) in common_ids {
+            let page = if page_number < self.collection_file.number_of_pages() {
+                Some(self.collection_file.read_page(page_number)?)
+            } else {
+                None
+            };
+            for id in ids {
+                let self_digest = match page.as_ref().and_then(|page| page.find_document(id)) {
+                    Some(document) => Some(digest(&document)?),
+                    None => None,
+                };
+                if self_digest != other_digests.get(&id).copied() {
+                    changed.push(id);
+                }
+            }
+        }
+
+        Ok(CollectionDiff {
+            only_in_self,
+            only_in_other,
+            changed,
+        })
+    }
+
+    /// Hash of the documents' bincode encoding taken in id order, so collections holding the
+    /// same documents hash the same whatever pages they are stored on.
+    fn content_hash(&self) -> Result<u64, CollectionError>
+    where
+        <T as HasId>::Id: Ord,
+    {
+        let mut documents = vec![];
+        for page_number in 0..self.collection_file.number_of_pages() {
+            let page = self.collection_file.read_page(page_number)?;
+            for document in page.documents().iter() {
+                documents.push((document.id(), bincode::serialize(document)?));
+            }
+        }
+        for document in self.pending_batch.iter().flatten() {
+            documents.push((document.id(), bincode::serialize(document)?));
+        }
+        documents.sort_by_key(|(id, _)| *id);
+
+        let mut hasher = FnvHasher::default();
+        for (_, encoded) in documents.iter() {
+            hasher.write_u64(encoded.len() as u64);
+            hasher.write(encoded);
+        }
+
+        Ok(hasher.finish())
+    }
+
+    /// Ids and serialized sizes of the documents larger than `min_bytes`, largest first.
+    fn large_documents(
+        &self,
+        min_bytes: u64,
+    ) -> Result<Vec<(<T as HasId>::Id, u64)>, CollectionError> {
+        let serialization_config = self.collection_file.serialization_config();
+        let mut large_documents = vec![];
+
+        for page in self.collection_file.non_empty_pages() {
+            for document in page?.documents().iter() {
+                let document_size = serialization_config.serialized_size(document)?;
+                if document_size > min_bytes {
+                    large_documents.push((document.id(), document_size));
+                }
+            }
+        }
+        large_documents.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        Ok(large_documents)
+    }
+
+    /// Up to `n` documents picked at random among the indexed ids, the same `seed` picks the
+    /// same documents. Documents of a pending batch are left out.
+    fn sample(&self, n: usize, seed: u64) -> Result<Vec<T>, CollectionError> {
+        // The index iterates in a per process order, ids are sorted by a stable hash first.
+        let mut entries: Vec<(u64, u64, <T as HasId>::Id)> = self
+            .id_index()?
+            .iter()
+            .map(|(id, page_number)| {
+                let mut hasher = FnvHasher::default();
+                id.hash(&mut hasher);
+                (hasher.finish(), *page_number, *id)
+            })
+            .collect();
+        entries.sort_by_key(|(hash, page_number, _)| (*hash, *page_number));
+
+        // Partial Fisher-Yates shuffle, drawing from splitmix64.
+        let picked = n.min(entries.len());
+        let mut state = seed;
+        for i in 0..picked {
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^= z >> 31;
+            let j = i + (z % (entries.len() - i) as u64) as usize;
+            entries.swap(i, j);
+        }
+
+        let mut ids_by_page: BTreeMap<u64, Vec<<T as HasId>::Id>> = BTreeMap::new();
+        for (_, page_number, id) in entries.into_iter().take(picked) {
+            ids_by_page.entry(page_number).or_default().push(id);
+        }
+
+        let mut documents = Vec::with_capacity(picked);
+        for (page_number, ids) in ids_by_page {
+            if page_number >= self.collection_file.number_of_pages() {
+                continue;
+            }
+            let page = self.collection_file.read_page(page_number)?;
+            documents.extend(ids.into_iter().filter_map(|id| page.find_document(id)));
+        }
+
+        Ok(documents)
+    }
+
+    /// Documents stored on pages `start_page` and after. Pages are appended as the collection
+    /// grows, but documents that fit on an earlier page are still written there.
+    fn documents_from_page(&self, start_page: u64) -> Result<Vec<T>, CollectionError> {
+        let mut documents = vec![];
+
+        for page_number in start_page..self.collection_file.number_of_pages() {
+            let page = self.collection_file.read_page(page_number)?;
+            documents.extend(page.documents().iter().cloned());
+        }
+
+        Ok(documents)
+    }
+
+    /// Ids of the documents stored on each page, the inverse of the index.
+    fn page_id_map(&self) -> Result<HashMap<u64, Vec<<T as HasId>::Id>>, CollectionError> {
+        let mut page_id_map = HashMap::new();
+
+        for page_number in 0..self.collection_file.number_of_pages() {
+            let page = self.collection_file.read_page(page_number)?;
+            page_id_map.insert(
+                page_number,
+                page.documents()
+                    .iter()
+                    .map(|document| document.id())
+                    .collect(),
+            );
+        }
+
+        Ok(page_id_map)
+    }
+
+    /// Share of the allocated page space that is free, between 0 and 1.
+    fn fragmentation_ratio(&self) -> f64 {
+        let mut free_space = 0;
+        let mut capacity = 0;
+
+        for page_number in 0..self.collection_file.number_of_pages() {
+            if let Ok(header) = self.collection_file.read_page_header(page_number) {
+                free_space += header.space_available();
+                capacity += self.collection_file.page_data_size();
+            }
+        }
+
+        if capacity == 0 {
+            return 0.0;
+        }
+
+        free_space as f64 / capacity as f64
+    }
+
+    /// Summary of the collection for monitoring: sizes, counts and the codec in use.
+    fn metadata_json(&self) -> serde_json::Value {
+        let mut number_of_documents = 0;
+        let mut free_space = 0;
+
+        for page_number in 0..self.collection_file.number_of_pages() {
+            if let Ok(header) = self.collection_file.read_page_header(page_number) {
+                number_of_documents += header.number_of_documents();
+                free_space += header.space_available();
+            }
+        }
+
+        json!({
+            "name": self.collection_file.name(),
+            "page_size": self.collection_file.page_size(),
+            "number_of_pages": self.collection_file.number_of_pages(),
+            "number_of_documents": number_of_documents,
+            "free_space": free_space,
+            "fragmentation_ratio": self.fragmentation_ratio(),
+            "codec": self.collection_file.serialization_config().codec(),
+        })
+    }
+
+    /// Passes every page to `f`, which can edit its documents through `documents_mut`. Pages
+    /// for which `f` returns true are written back, then the index is rebuilt.
+    fn for_each_page_mut<F: FnMut(&mut CollectionPage<T>) -> bool>(
+        &mut self,
+        mut f: F,
+    ) -> Result<(), CollectionError> {
+        self.forget_cached(None);
+
+        for page_number in 0..self.collection_file.number_of_pages() {
+            let mut page = self.collection_file.read_page(page_number)?;
+
+            if f(&mut page) {
+                page.defragment()?;
+                self.collection_file.write_page(&page)?;
+            }
+        }
+
+        self.id_to_page_map =
+            OnceLock::from(index_collection_id_with_hasher(&self.collection_file)?);
+        self.build_secondary_indexes()?;
+
+        Ok(())
+    }
+
+    /// Replaces every document by `f` of it, page by page, for migrations keeping the type.
+    /// Documents that no longer fit their page are moved to pages appended after the existing
+    /// ones, written before their old page so a crash never loses them, and the index is
+    /// rebuilt at the end.
+    fn transform_in_place(&mut self, f: impl Fn(T) -> T) -> Result<(), CollectionError> {
+        self.forget_cached(None);
+
+        let mut relocation_page: Option<CollectionPage<T>> = None;
+
+        for page_number in 0..self.collection_file.number_of_pages() {
+            let mut page = self.collection_file.read_page(page_number)?;
+            let documents = std::mem::take(page.documents_mut());
+            page.defragment()?;
+
+            let mut relocated = false;
+            for document in documents {
+                let document = f(document);
+                let document_size = self.checked_document_size(&document)?;
+
+                match page.insert_document(&document) {
+                    Ok(_) => continue,
+                    Err(CollectionPageError::NoFreeSpaceAvailable) => {}
+                    Err(e) => return Err(CollectionError::PageError(e)),
+                }
+
+                let target = match relocation_page.take() {
+                    Some(target) if self.has_room(target.header(), document_size)? => target,
+                    previous => {
+                        if let Some(previous) = previous {
+                            self.collection_file.write_page(&previous)?;
+                        }
+                        self.collection_file
+                            .new_page(self.collection_file.number_of_pages())
+                    }
+                };
+                let target = relocation_page.insert(target);
+                target.insert_document(&document)?;
+                relocated = true;
+            }
+
+            if relocated {
+                if let Some(target) = relocation_page.as_ref() {
+                    self.collection_file.write_page(target)?;
+                }
+                self.sync_if_durable()?;
+            }
+            self.collection_file.write_page(&page)?;
+        }
+
+        if let Some(pending) = self.pending_batch.take() {
+            self.pending_batch = Some(pending.into_iter().map(&f).collect());
+        }
+
+        self.sync_if_durable()?;
+        self.id_to_page_map =
+            OnceLock::from(index_collection_id_with_hasher(&self.collection_file)?);
+        self.build_secondary_indexes()?;
+
+        Ok(())
+    }
+
+    /// Rewrites a single page with its free space recomputed from the live documents,
+    /// returning the bytes reclaimed. Cheaper than compacting the whole collection.
+    fn defragment_page(&mut self, page_number: u64) -> Result<usize, CollectionError> {
+        let mut page = self.collection_file.read_page(page_number)?;
+        let reclaimed = page.defragment()?;
+
+        self.collection_file.write_page(&page)?;
+
+        Ok(reclaimed as usize)
+    }
+
+    /// Empties the pages filled below `min_fill` (a share of the page data size) by moving
+    /// their documents to fuller pages, then to pages emptied before, then to new pages, and
+    /// returns how many pages were emptied. Other pages are only written when they receive
+    /// documents. Each document is written to its new page before its old page is cleared;
+    /// emptied pages stay in the file and are reused by later inserts.
+    fn compact_sparse(&mut self, min_fill: f64) -> Result<usize, CollectionError> {
+        self.forget_cached(None);
+
+        let page_data_size = self.collection_file.page_data_size() as f64;
+        let mut sparse_pages = BTreeSet::new();
+        for page_number in 0..self.collection_file.number_of_pages() {
+            let header = self.collection_file.read_page_header(page_number)?;
+            let fill = 1.0 - header.space_available() as f64 / page_data_size;
+
+            if header.number_of_documents() > 0 && fill < min_fill {
+                sparse_pages.insert(page_number);
+            }
+        }
+
+        let compacted = sparse_pages.len();
+        while let Some(page_number) = sparse_pages.pop_first() {
+            let mut page = self.collection_file.read_page(page_number)?;
+
+            for document in page.documents().iter() {
+                let document_size = self.checked_document_size(document)?;
+                let mut target =
+                    self.compaction_target(&sparse_pages, page_number, document_size)?;
+                target.insert_document(document)?;
+                self.collection_file.write_page(&target)?;
+            }
+            self.sync_if_durable()?;
+
+            page.documents_mut().clear();
+            page.defragment()?;
+            self.collection_file.write_page(&page)?;
+            self.sync_if_durable()?;
+        }
+
+        if compacted > 0 {
+            self.id_to_page_map =
+                OnceLock::from(index_collection_id_with_hasher(&self.collection_file)?);
+            self.build_secondary_indexes()?;
+        }
+
+        Ok(compacted)
+    }
+
+    /// Page a document drained by `compact_sparse` moves to: the first page holding documents
+    /// with room for it, else the first empty one, else a new page. Pages still to be drained
+    /// and the page being drained are skipped.
+    fn compaction_target(
+        &self,
+        sparse_pages: &BTreeSet<u64>,
+        source: u64,
+        document_size: u64,
+    ) -> Result<CollectionPage<T>, CollectionError> {
+        let mut first_empty = None;
+
+        for page_number in 0..self.collection_file.number_of_pages() {
+            if page_number == source || sparse_pages.contains(&page_number) {
+                continue;
+            }
+
+            let header = self.collection_file.read_page_header(page_number)?;
+            if header.number_of_documents() == 0 {
+                first_empty = first_empty.or(Some(page_number));
+            } else if self.has_room(&header, document_size)? {
+                return Ok(self.collection_file.read_page(page_number)?);
+            }
+        }
+
+        match first_empty {
+            Some(page_number) => {
+                let mut page = self.collection_file.read_page(page_number)?;
+                if self.collection_file.slot_directory() {
+                    page.enable_slot_directory();
+                }
+
+                Ok(page)
+            }
+            None => Ok(self
+                .collection_file
+                .new_page(self.collection_file.number_of_pages())),
+        }
+    }
+
+    /// Pages whose header disagrees with the free space recomputed from their documents, as
+    /// `(page, recorded, actual)`. A drift means inserts pick pages from wrong numbers until
+    /// the page is defragmented.
+    fn verify_free_space_map(&self) -> Result<Vec<(u64, u64, u64)>, CollectionError> {
+        let mut mismatches = vec![];
+
+        for page_number in 0..self.collection_file.number_of_pages() {
+            let page = self.collection_file.read_page(page_number)?;
+            let recorded = page.header().space_available();
+            let actual = page.recomputed_space_available()?;
+
+            if recorded != actual {
+                mismatches.push((page_number, recorded, actual));
+            }
+        }
+
+        Ok(mismatches)
+    }
+
+    /// Whether enough space is free across pages for a compaction to be worth it.
+    fn needs_compaction(&self, threshold: f64) -> bool {
+        self.fragmentation_ratio() > threshold
+    }
+
+    /// Whether updating to `doc_update` would move the document to another page
+    /// because its page doesn't have room for the new version.
+    fn would_relocate(&self, doc_update: &T) -> Result<bool, CollectionError> {
+        let doc_id = doc_update.id();
+        let page_number = self
+            .id_index()?
+            .get(&doc_id)
+            .ok_or(CollectionError::NotFoundError)?;
+
+        let page = self.collection_file.read_page(*page_number)?;
+        let current = page
+            .find_document(doc_id)
+            .ok_or(CollectionError::NotFoundError)?;
+
+        let serialization_config = self.collection_file.serialization_config();
+        let current_size = serialization_config.serialized_size(&current)?;
+        let update_size = serialization_config.serialized_size(doc_update)?;
+
+        Ok(update_size > page.header.space_available() + current_size)
+    }
+
+    fn sync_if_durable(&self) -> Result<(), CollectionError> {
+        if self.durable {
+            self.collection_file.sync_data()?;
+        }
+
+        Ok(())
+    }
+
+    /// Updates the document if its id is already stored or pending in a batch, inserts it
+    /// otherwise.
+    pub fn upsert_one(&mut self, doc: &T) -> Result<(), CollectionError> {
+        if self.pending_document(doc.id()).is_some() || self.id_index()?.contains_key(&doc.id()) {
+            self.update_one(doc)
+        } else {
+            self.insert_one(doc)
+        }
+    }
+}
+
+/// Name of the `shard`-th collection written by `export_pages_as_shards`.
+fn shard_name(name: &str, shard: u64) -> String {
+    format!("{}_shard_{}", name, shard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collection_file::ReadPolicy;
+    use crate::collection_page;
+    use crate::document::HasId;
+    use crate::document_generator::{DocumentGenerator, SizeDistribution};
+    use crate::serialization::Codec;
+    use crate::storage::FaultyStorage;
+    use serde_derive::{Deserialize, Serialize};
+    use std::fs::OpenOptions;
+    use tempfile::tempdir;
+
+    #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+    struct MyDocument {
+        id: u64,
+        name: String,
+    }
+
+    impl HasId for MyDocument {
+        type Id = u64;
+
+        fn id(&self) -> u64 {
+            self.id
+        }
+    }
+
+    impl crate::document::ParseId for MyDocument {
+        fn parse_id(s: &str) -> Result<u64, crate::document::IdParseError> {
+            s.parse()
+                .map_err(|e| crate::document::IdParseError::new(s, e))
+        }
+    }
+
+    /// Documents with names of 500 to 1500 bytes, the same for a given seed.
+    fn gen_documents(count: usize, seed: u64) -> Vec<MyDocument> {
+        DocumentGenerator::new(seed)
+            .with_sizes(SizeDistribution::Uniform {
+                min: 500,
+                max: 1_500,
+            })
+            .generate(count, |id, name| MyDocument { id, name })
+    }
+
+    #[test]
+    fn test_insert_one_find_one_by_id() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        let document: MyDocument = MyDocument {
+            id: 0,
+            name: String::from("test1"),
+        };
+
+        collection.insert_one(&document).unwrap();
+
+        let doc_from_collection = collection.find_by_id(0).unwrap();
+
+        assert_eq!(document, doc_from_collection);
+    }
+
+    #[test]
+    fn test_insert_find_all_collection() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        let documents: Vec<MyDocument> = vec![
+            MyDocument {
+                id: 0,
+                name: String::from("test1"),
+            },
+            MyDocument {
+                id: 1,
+                name: String::from("test2"),
+            },
+        ];
+
+        for document in &documents {
+            collection.insert_one(&document).unwrap();
+        }
+
+        let doc_from_collection = collection.find_by(|_| true).unwrap();
+
+        assert_eq!(documents, doc_from_collection);
+    }
+
+    #[test]
+    fn test_insert_find_by_collection() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        let documents: Vec<MyDocument> = vec![
+            MyDocument {
+                id: 0,
+                name: String::from("test1"),
+            },
+            MyDocument {
+                id: 1,
+                name: String::from("test2"),
+            },
+            MyDocument {
+                id: 2,
+                name: String::from("test3"),
+            },
+            MyDocument {
+                id: 3,
+                name: String::from("test4"),
+            },
+        ];
+
+        for document in &documents {
+            collection.insert_one(&document).unwrap();
+        }
+
+        let doc_from_collection = collection.find_by(|doc| doc.id() % 2 == 0).unwrap();
+
+        assert_eq!(
+            vec![
+                MyDocument {
+                    id: 0,
+                    name: String::from("test1"),
+                },
+                MyDocument {
+                    id: 2,
+                    name: String::from("test3"),
+                },
+            ],
+            doc_from_collection
+        );
+    }
+
+    #[test]
+    fn test_validators_are_enforced_in_chain() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name)
+            .with_validator(Box::new(|doc: &MyDocument| {
+                if doc.id == 0 {
+                    return Err(String::from("id 0 is reserved"));
+                }
+                Ok(())
+            }))
+            .with_validator(Box::new(|doc: &MyDocument| {
+                if doc.name.is_empty() {
+                    return Err(String::from("name is empty"));
+                }
+                Ok(())
+            }));
+
+        let reserved_id = collection.insert_one(&MyDocument {
+            id: 0,
+            name: String::from("test1"),
+        });
+        assert!(matches!(
+            reserved_id,
+            Err(CollectionError::ValidationFailed(reason)) if reason == "id 0 is reserved"
+        ));
+
+        let empty_name = collection.insert_one(&MyDocument {
+            id: 1,
+            name: String::new(),
+        });
+        assert!(matches!(
+            empty_name,
+            Err(CollectionError::ValidationFailed(reason)) if reason == "name is empty"
+        ));
+
+        let document = MyDocument {
+            id: 1,
+            name: String::from("test1"),
+        };
+        collection.insert_one(&document).unwrap();
+
+        assert_eq!(collection.find_by(|_| true).unwrap(), vec![document]);
+    }
+
+    #[test]
+    fn test_validators_guard_updates_and_upserts() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name).with_validator(
+            Box::new(|doc: &MyDocument| {
+                if doc.name.is_empty() {
+                    return Err(String::from("name is empty"));
+                }
+                Ok(())
+            }),
+        );
+
+        let document = MyDocument {
+            id: 1,
+            name: String::from("test1"),
+        };
+        collection.insert_one(&document).unwrap();
+
+        let emptied = MyDocument {
+            id: 1,
+            name: String::new(),
+        };
+        assert!(matches!(
+            collection.update_one(&emptied),
+            Err(CollectionError::ValidationFailed(_))
+        ));
+        assert!(matches!(
+            collection.upsert_one(&emptied),
+            Err(CollectionError::ValidationFailed(_))
+        ));
+        assert!(matches!(
+            collection.upsert_one(&MyDocument {
+                id: 2,
+                name: String::new(),
+            }),
+            Err(CollectionError::ValidationFailed(_))
+        ));
+
+        let reopened = Collection::<MyDocument>::new("test", dir_name);
+        assert_eq!(reopened.find_by(|_| true).unwrap(), vec![document]);
+    }
+
+    #[test]
+    fn test_migrate_documents_on_build() {
+        #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+        struct UserDocumentV1 {
+            id: u64,
+            name: String,
+            age: u8,
+        }
+
+        impl HasId for UserDocumentV1 {
+            type Id = u64;
+
+            fn id(&self) -> u64 {
+                self.id
+            }
+
+            fn schema_version() -> u32 {
+                1
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in 0..3 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("test{}", id),
+                })
+                .unwrap();
+        }
+
+        let not_migrated = Collection::<UserDocumentV1>::builder("test", dir_name).build();
+        assert!(matches!(
+            not_migrated,
+            Err(CollectionError::SchemaVersionMismatch)
+        ));
+
+        let migrated = Collection::<UserDocumentV1>::builder("test", dir_name)
+            .with_migration(Box::new(|old: MyDocument| UserDocumentV1 {
+                id: old.id,
+                name: old.name,
+                age: 18,
+            }))
+            .build()
+            .unwrap();
+
+        for id in 0..3 {
+            assert_eq!(
+                migrated.find_by_id(id),
+                Some(UserDocumentV1 {
+                    id,
+                    name: format!("test{}", id),
+                    age: 18,
+                })
+            );
+        }
+
+        let reopened = Collection::<UserDocumentV1>::builder("test", dir_name)
+            .build()
+            .unwrap();
+        assert_eq!(reopened.find_by(|_| true).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_get_or_insert_with() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        let document = MyDocument {
+            id: 0,
+            name: String::from("test1"),
+        };
+
+        let inserted = collection
+            .get_or_insert_with(0, || document.clone())
+            .unwrap();
+        assert_eq!(inserted, document);
+        assert_eq!(collection.find_by_id(0), Some(document.clone()));
+
+        let existing = collection
+            .get_or_insert_with(0, || panic!("should not be called on a hit"))
+            .unwrap();
+        assert_eq!(existing, document);
+
+        let mismatch = collection.get_or_insert_with(1, || MyDocument {
+            id: 2,
+            name: String::from("test2"),
+        });
+        assert!(matches!(mismatch, Err(CollectionError::IdMismatchError)));
+        assert_eq!(collection.find_by(|_| true).unwrap(), vec![document]);
+    }
+
+    #[test]
+    fn test_normalizer_is_applied_before_storage() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection =
+            Collection::<MyDocument>::new("test", dir_name).with_normalizer(|doc| MyDocument {
+                id: doc.id,
+                name: doc.name.trim().to_lowercase(),
+            });
+
+        collection
+            .insert_one(&MyDocument {
+                id: 0,
+                name: String::from(" TeSt1 "),
+            })
+            .unwrap();
+        assert_eq!(collection.find_by_id(0).unwrap().name, "test1");
+
+        collection
+            .update_one(&MyDocument {
+                id: 0,
+                name: String::from("UPDATED"),
+            })
+            .unwrap();
+        assert_eq!(collection.find_by_id(0).unwrap().name, "updated");
+
+        collection
+            .upsert_one(&MyDocument {
+                id: 1,
+                name: String::from("Upserted"),
+            })
+            .unwrap();
+        assert_eq!(collection.find_by_id(1).unwrap().name, "upserted");
+    }
+
+    #[test]
+    fn test_fragmentation_ratio() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for document in gen_documents(124, 1) {
+            collection.insert_one(&document).unwrap();
+        }
+
+        assert_eq!(collection.collection_file.number_of_pages(), 2);
+        assert!(collection.fragmentation_ratio() < 0.05);
+        assert!(!collection.needs_compaction(0.5));
+
+        for id in 0..100 {
+            collection.delete_one(id).unwrap();
+        }
+
+        assert!(collection.fragmentation_ratio() > 0.75);
+        assert!(collection.needs_compaction(0.5));
+        assert_eq!(collection.find_by(|_| true).unwrap().len(), 24);
+    }
+
+    #[test]
+    fn test_group_by() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in 0..7 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("test{}", id),
+                })
+                .unwrap();
+        }
+
+        collection.begin_batch();
+        collection
+            .insert_one(&MyDocument {
+                id: 7,
+                name: "test7".to_string(),
+            })
+            .unwrap();
+
+        let groups = collection.group_by(|doc| doc.id % 3).unwrap();
+        let mut group_ids: Vec<(u64, Vec<u64>)> = groups
+            .into_iter()
+            .map(|(key, docs)| (key, docs.iter().map(|doc| doc.id).collect()))
+            .collect();
+        group_ids.sort();
+
+        assert_eq!(
+            group_ids,
+            vec![(0, vec![0, 3, 6]), (1, vec![1, 4, 7]), (2, vec![2, 5])]
+        );
+
+        let counts = collection.group_counts(|doc| doc.id % 3).unwrap();
+        assert_eq!(counts.get(&0), Some(&3));
+        assert_eq!(counts.get(&1), Some(&3));
+        assert_eq!(counts.get(&2), Some(&2));
+
+        collection.commit_batch().unwrap();
+        let path = format!("{}/test.collection", dir_name);
+        let mut bytes = std::fs::read(&path).unwrap();
+        let position = bytes
+            .windows(5)
+            .position(|window| window == b"test4")
+            .unwrap();
+        bytes[position] = 0xFF;
+        std::fs::write(&path, bytes).unwrap();
+
+        assert!(collection.group_by(|doc| doc.id % 3).is_err());
+        assert!(collection.group_counts(|doc| doc.id % 3).is_err());
+    }
+
+    #[test]
+    fn test_would_relocate() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        collection
+            .insert_one(&MyDocument {
+                id: 0,
+                name: String::from("test1"),
+            })
+            .unwrap();
+        collection
+            .insert_one(&MyDocument {
+                id: 1,
+                name: "a".repeat(60_000),
+            })
+            .unwrap();
+
+        let same_size = MyDocument {
+            id: 0,
+            name: String::from("test2"),
+        };
+        let outgrown = MyDocument {
+            id: 0,
+            name: "a".repeat(10_000),
+        };
+
+        assert!(!collection.would_relocate(&same_size).unwrap());
+        assert!(collection.would_relocate(&outgrown).unwrap());
+
+        collection.update_one(&outgrown).unwrap();
+        assert_eq!(collection.id_index().unwrap().get(&0), Some(&1));
+        assert_eq!(collection.find_by_id(0), Some(outgrown));
+    }
+
+    #[test]
+    fn test_upsert_inside_a_batch_updates_pending_documents() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        collection.begin_batch();
+        for name in ["first", "second"] {
+            collection
+                .upsert_one(&MyDocument {
+                    id: 1,
+                    name: name.to_string(),
+                })
+                .unwrap();
+        }
+        collection.commit_batch().unwrap();
+
+        assert_eq!(collection.find_by(|_| true).unwrap().len(), 1);
+        assert_eq!(collection.find_by_id(1).unwrap().name, "second");
+    }
+
+    #[test]
+    fn test_batch_reads_its_own_writes() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        let document = MyDocument {
+            id: 0,
+            name: String::from("test1"),
+        };
+
+        collection.begin_batch();
+        collection.insert_one(&document).unwrap();
+
+        assert_eq!(collection.find_by_id(0), Some(document.clone()));
+        assert_eq!(
+            collection.find_by(|_| true).unwrap(),
+            vec![document.clone()]
+        );
+        assert!(matches!(
+            collection.insert_one(&document),
+            Err(CollectionError::DuplicateError)
+        ));
+        assert!(collection
+            .collection_file
+            .read_page(0)
+            .unwrap()
+            .documents()
+            .is_empty());
+
+        collection.commit_batch().unwrap();
+
+        let reopened = Collection::<MyDocument>::new("test", dir_name);
+        assert_eq!(reopened.find_by_id(0), Some(document));
+    }
+
+    #[test]
+    fn test_defragment_page_keeps_documents() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in 0..4 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("test{}", id),
+                })
+                .unwrap();
+        }
+        collection.delete_one(1).unwrap();
+
+        assert_eq!(collection.defragment_page(0).unwrap(), 0);
+        assert_eq!(collection.find_by(|_| true).unwrap().len(), 3);
+        assert!(collection.defragment_page(1).is_err());
+    }
+
+    #[test]
+    fn test_put_and_get_blob() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        collection
+            .insert_one(&MyDocument {
+                id: 0,
+                name: String::from("test1"),
+            })
+            .unwrap();
+
+        let blob: Vec<u8> = (0..200_000).map(|i| (i % 251) as u8).collect();
+
+        assert!(matches!(
+            collection.put_blob(1, &blob),
+            Err(CollectionError::NotFoundError)
+        ));
+        collection.put_blob(0, &blob).unwrap();
+
+        assert_eq!(collection.get_blob(0), Some(blob.clone()));
+        assert_eq!(collection.collection_file.number_of_pages(), 1);
+
+        let reopened = Collection::<MyDocument>::new("test", dir_name);
+        assert_eq!(reopened.get_blob(0), Some(blob));
+        assert_eq!(reopened.get_blob(1), None);
+    }
+
+    #[test]
+    fn test_allocate_ids_reserves_disjoint_blocks() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        let first = collection.allocate_ids(10).unwrap();
+        let second = collection.allocate_ids(5).unwrap();
+        assert_eq!(first, 0..10);
+        assert_eq!(second, 10..15);
+
+        // Another handle on the same sidecar continues after both blocks.
+        let mut other = Collection::<MyDocument>::new("test", dir_name);
+        assert_eq!(IdSequence::open("test", dir_name).next_id().unwrap(), 15);
+        assert_eq!(other.allocate_ids(1).unwrap(), 15..16);
+        assert_eq!(collection.allocate_ids(0).unwrap(), 16..16);
+    }
+
+    #[test]
+    fn test_export_and_import_shards() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let shard_dir = tempdir().unwrap();
+        let shard_binding = shard_dir.into_path();
+        let shard_dir_name = shard_binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        // Three documents per page, so 4 pages.
+        let documents: Vec<MyDocument> = (0..11)
+            .map(|id| MyDocument {
+                id,
+                name: "a".repeat(20_000),
+            })
+            .collect();
+        collection.insert_many(&documents).unwrap();
+
+        collection
+            .export_pages_as_shards(shard_dir_name, 3)
+            .unwrap();
+
+        // Shard 0 gets pages 0 and 3, the others a page each.
+        let shard_sizes: Vec<usize> = (0..3)
+            .map(|shard| {
+                Collection::<MyDocument>::new(&shard_name("test", shard), shard_dir_name)
+                    .find_by(|_| true)
+                    .unwrap()
+                    .len()
+            })
+            .collect();
+        assert_eq!(shard_sizes, vec![5, 3, 3]);
+
+        let mut recombined = Collection::<MyDocument>::new("recombined", dir_name);
+        assert_eq!(
+            recombined.import_shards(shard_dir_name, "test", 3).unwrap(),
+            11
+        );
+        assert_eq!(recombined.find_all_ordered().unwrap(), documents);
+    }
+
+    #[test]
+    fn test_document_cache_serves_repeated_lookups() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::builder("test", dir_name)
+            .with_document_cache(16)
+            .build()
+            .unwrap();
+
+        for id in 0..3 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("test{}", id),
+                })
+                .unwrap();
+        }
+
+        let before = collection.io_stats();
+        assert_eq!(collection.find_by_id(1).unwrap().name, "test1");
+        assert_eq!(collection.io_stats().pages_read - before.pages_read, 1);
+        assert_eq!(collection.find_by_id(1).unwrap().name, "test1");
+        assert_eq!(collection.io_stats().pages_read - before.pages_read, 1);
+
+        collection
+            .update_one(&MyDocument {
+                id: 1,
+                name: "updated".to_string(),
+            })
+            .unwrap();
+        let before = collection.io_stats();
+        assert_eq!(collection.find_by_id(1).unwrap().name, "updated");
+        assert_eq!(collection.io_stats().pages_read - before.pages_read, 1);
+
+        collection.delete_one(1).unwrap();
+        assert!(collection.find_by_id(1).is_none());
+    }
+
+    #[test]
+    fn test_page_cache_serves_lookups_once_warmed() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+        // Three documents per page.
+        for id in 0..7 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+        drop(collection);
+
+        // Opening the collection reads every page to build the index, which fills the cache.
+        let mut collection = Collection::<MyDocument>::builder("test", dir_name)
+            .with_page_cache(4)
+            .build()
+            .unwrap();
+        let before = collection.io_stats();
+        for id in 0..7 {
+            assert_eq!(collection.find_by_id(id).unwrap().id, id);
+        }
+        assert_eq!(collection.io_stats().pages_read, before.pages_read);
+
+        collection
+            .update_one(&MyDocument {
+                id: 4,
+                name: "b".repeat(20_000),
+            })
+            .unwrap();
+        collection
+            .warm_cache(0..collection.collection_file.number_of_pages())
+            .unwrap();
+        let before = collection.io_stats();
+        for id in 0..7 {
+            assert_eq!(collection.find_by_id(id).unwrap().id, id);
+        }
+        let after = collection.io_stats();
+        assert_eq!(after.pages_read, before.pages_read);
+        assert_eq!(after.cache_hits - before.cache_hits, 7);
+    }
+
+    #[test]
+    fn test_verify_free_space_map_reports_drift() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        // Three documents per page.
+        for id in 0..6 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+        assert!(collection.verify_free_space_map().unwrap().is_empty());
+
+        // An edit written back without `defragment` leaves the header behind.
+        let mut page = collection.collection_file.read_page(1).unwrap();
+        let recorded = page.header().space_available();
+        page.documents_mut()[0].name = "a".repeat(19_000);
+        collection.collection_file.write_page(&page).unwrap();
+
+        assert_eq!(
+            collection.verify_free_space_map().unwrap(),
+            vec![(1, recorded, recorded + 1_000)]
+        );
+
+        collection.defragment_page(1).unwrap();
+        assert!(collection.verify_free_space_map().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_transform_in_place_relocates_grown_documents() {
+        #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+        struct Counter {
+            id: u64,
+            value: u64,
+            padding: String,
+        }
+
+        impl HasId for Counter {
+            type Id = u64;
+
+            fn id(&self) -> u64 {
+                self.id
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<Counter>::new("test", dir_name);
+
+        // Document 0 fills most of page 0 once its padding doubles, pushing document 1 out.
+        for (id, value) in [(0, 20_000), (1, 15_000), (2, 10), (3, 20)] {
+            collection
+                .insert_one(&Counter {
+                    id,
+                    value,
+                    padding: "a".repeat(value as usize),
+                })
+                .unwrap();
+        }
+        assert_eq!(collection.collection_file.number_of_pages(), 1);
+
+        collection
+            .transform_in_place(|mut counter| {
+                counter.value *= 2;
+                counter.padding = "a".repeat(counter.value as usize);
+                counter
+            })
+            .unwrap();
+
+        assert_eq!(collection.collection_file.number_of_pages(), 2);
+        for (id, value) in [(0, 40_000), (1, 30_000), (2, 20), (3, 40)] {
+            let counter = collection.find_by_id(id).unwrap();
+            assert_eq!(counter.value, value);
+            assert_eq!(counter.padding.len(), value as usize);
+        }
+        assert_eq!(collection.find_by(|_| true).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_compact_sparse_only_drains_sparse_pages() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        // Three documents per page, pages 1 and 3 are left with one document each.
+        for id in 0..12 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+        for id in [4, 5, 10, 11] {
+            collection.delete_one(id).unwrap();
+        }
+
+        let before = collection.io_stats();
+        assert_eq!(collection.compact_sparse(0.5).unwrap(), 2);
+
+        // Full pages have no room and aren't written, both documents end up on a new page.
+        // Two writes to the new page and one per emptied page.
+        assert_eq!(
+            collection.io_stats().pages_written - before.pages_written,
+            4
+        );
+        let page_id_map = collection.page_id_map().unwrap();
+        assert_eq!(page_id_map[&0], vec![0, 1, 2]);
+        assert!(page_id_map[&1].is_empty());
+        assert_eq!(page_id_map[&2], vec![6, 7, 8]);
+        assert!(page_id_map[&3].is_empty());
+        assert_eq!(page_id_map[&4], vec![3, 9]);
+        for id in [3, 9] {
+            assert_eq!(collection.find_by_id(id).unwrap().id, id);
+        }
+
+        assert_eq!(collection.compact_sparse(0.5).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_scan_budget_aborts_large_find_by() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection =
+            Collection::<MyDocument>::new("test", dir_name).with_scan_budget(50_000);
+
+        for id in 0..10 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+
+        // Two matches fit in the budget, a third one goes over it.
+        assert_eq!(collection.find_by(|d| d.id < 2).unwrap().len(), 2);
+        assert!(matches!(
+            collection.find_by(|_| true),
+            Err(CollectionError::ScanBudgetExceededError)
+        ));
+
+        let collection = crate::sync_collection::SyncCollection::new(collection);
+        assert!(matches!(
+            collection.find_by(|_| true),
+            Err(CollectionError::ScanBudgetExceededError)
+        ));
+    }
+
+    #[test]
+    fn test_join_orders_to_users() {
+        #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+        struct Order {
+            id: u64,
+            user_id: u64,
+            item: String,
+        }
+
+        impl HasId for Order {
+            type Id = u64;
+
+            fn id(&self) -> u64 {
+                self.id
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut users = Collection::<MyDocument>::new("users", dir_name);
+        let mut orders = Collection::<Order>::new("orders", dir_name);
+
+        // Three users per page.
+        for id in 0..6 {
+            users
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("user{}", id).repeat(4_000),
+                })
+                .unwrap();
+        }
+        for (id, user_id) in [(0, 4), (1, 0), (2, 4), (3, 9)] {
+            orders
+                .insert_one(&Order {
+                    id,
+                    user_id,
+                    item: format!("item{}", id),
+                })
+                .unwrap();
+        }
+
+        let before = users.io_stats();
+        let mut joined = orders.join_by(&users, |order| order.user_id).unwrap();
+        assert_eq!(users.io_stats().pages_read - before.pages_read, 2);
+
+        joined.sort_by_key(|(order, _)| order.id);
+        let pairs: Vec<(u64, Option<u64>)> = joined
+            .iter()
+            .map(|(order, user)| (order.id, user.as_ref().map(|user| user.id)))
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![(0, Some(4)), (1, Some(0)), (2, Some(4)), (3, None)]
+        );
+
+        let orders = orders.with_scan_budget(10);
+        assert!(matches!(
+            orders.join_by(&users, |order| order.user_id),
+            Err(CollectionError::ScanBudgetExceededError)
+        ));
+    }
+
+    #[test]
+    fn test_reserve_grows_the_index_before_a_bulk_insert() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+        collection
+            .insert_one(&MyDocument {
+                id: 0,
+                name: "test0".to_string(),
+            })
+            .unwrap();
+
+        collection.reserve(10_000);
+        let capacity = collection.id_index().unwrap().capacity();
+        assert!(capacity >= 10_001);
+
+        let documents: Vec<MyDocument> = (1..10_001)
+            .map(|id| MyDocument {
+                id,
+                name: format!("test{}", id),
+            })
+            .collect();
+        collection.insert_many(&documents).unwrap();
+
+        assert_eq!(collection.id_index().unwrap().len(), 10_001);
+        assert_eq!(collection.id_index().unwrap().capacity(), capacity);
+    }
+
+    #[test]
+    fn test_meta_is_persisted_across_reopens() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        assert_eq!(collection.get_meta("last_sync_cursor"), None);
+        collection.set_meta("last_sync_cursor", b"41").unwrap();
+        collection.set_meta("last_sync_cursor", b"42").unwrap();
+        collection.set_meta("owner", b"sync").unwrap();
+
+        let reopened = Collection::<MyDocument>::new("test", dir_name);
+
+        assert_eq!(reopened.get_meta("last_sync_cursor"), Some(b"42".to_vec()));
+        assert_eq!(reopened.get_meta("owner"), Some(b"sync".to_vec()));
+        assert_eq!(reopened.get_meta("missing"), None);
+    }
+
+    #[test]
+    fn test_parse_id_before_lookup() {
+        use crate::document::{IdParseError, ParseId};
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+        collection
+            .insert_one(&MyDocument {
+                id: 42,
+                name: "test42".to_string(),
+            })
+            .unwrap();
+
+        let id = MyDocument::parse_id("42").unwrap();
+        assert_eq!(collection.find_by_id(id).unwrap().name, "test42");
+
+        assert_eq!(
+            MyDocument::parse_id("forty-two"),
+            Err(IdParseError::new(
+                "forty-two",
+                "invalid digit found in string"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_crash_during_relocation_does_not_lose_document() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        collection
+            .insert_one(&MyDocument {
+                id: 0,
+                name: String::from("test1"),
+            })
+            .unwrap();
+        collection
+            .insert_one(&MyDocument {
+                id: 1,
+                name: "a".repeat(60_000),
+            })
+            .unwrap();
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(format!("{}/test.collection", dir_name))
+            .unwrap();
+        let mut crashing = Collection::<MyDocument>::builder("test", dir_name)
+            .with_storage(Box::new(FaultyStorage::failing_after(file, 1)))
+            .with_durability(true)
+            .build()
+            .unwrap();
+
+        let outgrown = MyDocument {
+            id: 0,
+            name: "a".repeat(10_000),
+        };
+        assert!(crashing.update_one(&outgrown).is_err());
+
+        let recovered = Collection::<MyDocument>::new("test", dir_name);
+        assert_eq!(recovered.find_by_id(0), Some(outgrown));
+        assert_eq!(recovered.find_by_id(1).unwrap().name.len(), 60_000);
+    }
+
+    #[test]
+    fn test_crash_during_relocation_keeps_the_new_copy_on_reopen() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        // Page 0 ends up empty, page 1 too full for document 1 to grow in place.
+        for (id, size) in [(0, 50_000), (1, 20_000), (2, 40_000)] {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(size),
+                })
+                .unwrap();
+        }
+        collection.delete_one(0).unwrap();
+        assert_eq!(*collection.id_index().unwrap().get(&1).unwrap(), 1);
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(format!("{}/test.collection", dir_name))
+            .unwrap();
+        let mut crashing = Collection::<MyDocument>::builder("test", dir_name)
+            .with_storage(Box::new(FaultyStorage::failing_nth_write(file, 2)))
+            .with_durability(true)
+            .build()
+            .unwrap();
+
+        // The new copy is written, removing the old one fails.
+        let outgrown = MyDocument {
+            id: 1,
+            name: "b".repeat(25_000),
+        };
+        assert!(crashing.update_one(&outgrown).is_err());
+
+        let recovered = Collection::<MyDocument>::new("test", dir_name);
+        assert_eq!(recovered.find_by_id(1), Some(outgrown));
+        assert_eq!(recovered.find_by_id(2).unwrap().name.len(), 40_000);
+    }
+
+    #[test]
+    fn test_derived_has_id() {
+        use rust_db_derive::HasId;
+
+        #[derive(Deserialize, Serialize, Clone, Debug, PartialEq, HasId)]
+        struct UserDocument {
+            name: String,
+            #[id]
+            user_id: u32,
+        }
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<UserDocument>::new("test", dir_name);
+
+        let document = UserDocument {
+            name: String::from("test1"),
+            user_id: 42,
+        };
+        collection.insert_one(&document).unwrap();
+
+        assert_eq!(document.id(), 42u32);
+        assert_eq!(collection.find_by_id(42), Some(document));
+    }
+
+    #[test]
+    fn test_prune_versions() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::builder("test", dir_name)
+            .with_versioning(true)
+            .build()
+            .unwrap();
+
+        for version in 0..6 {
+            collection
+                .upsert_one(&MyDocument {
+                    id: 0,
+                    name: format!("version{}", version),
+                })
+                .unwrap();
+        }
+
+        assert_eq!(collection.versions(0).unwrap().len(), 5);
+        assert_eq!(collection.prune_versions(0, 2).unwrap(), 3);
+
+        let reopened = Collection::<MyDocument>::builder("test", dir_name)
+            .with_versioning(true)
+            .build()
+            .unwrap();
+        let names: Vec<String> = reopened
+            .versions(0)
+            .unwrap()
+            .into_iter()
+            .map(|doc| doc.name)
+            .collect();
+
+        assert_eq!(names, vec!["version3", "version4"]);
+        assert_eq!(reopened.find_by_id(0).unwrap().name, "version5");
+        assert!(matches!(
+            Collection::<MyDocument>::new("test", dir_name).versions(0),
+            Err(CollectionError::VersioningDisabled)
+        ));
+    }
+
+    #[test]
+    fn test_import_iter_reports_progress() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        let mut reported = vec![];
+        let inserted = collection
+            .import_iter(
+                (0..1000).map(|id| MyDocument {
+                    id,
+                    name: format!("test{}", id),
+                }),
+                |count| reported.push(count),
+            )
+            .unwrap();
+
+        assert_eq!(inserted, 1000);
+        assert_eq!(reported, (1..=10).map(|i| i * 100).collect::<Vec<usize>>());
+
+        let duplicates = (995..1005).map(|id| MyDocument {
+            id,
+            name: format!("test{}", id),
+        });
+        assert!(matches!(
+            collection.import_iter(duplicates.clone(), |_| {}),
+            Err(CollectionError::DuplicateError)
+        ));
+
+        let mut collection = collection.with_import_options(ImportOptions {
+            progress_interval: 5,
+            on_duplicate: DuplicatePolicy::Skip,
+        });
+        let mut reported = 0;
+        let inserted = collection
+            .import_iter(duplicates, |_| reported += 1)
+            .unwrap();
+
+        assert_eq!(inserted, 5);
+        assert_eq!(reported, 2);
+        assert_eq!(collection.find_by(|_| true).unwrap().len(), 1005);
+    }
+
+    #[test]
+    fn test_view_as_reads_a_subset_of_fields_with_json() {
+        #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+        struct IdOnly {
+            id: u64,
+        }
+
+        impl HasId for IdOnly {
+            type Id = u64;
+
+            fn id(&self) -> u64 {
+                self.id
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::builder("test", dir_name)
+            .with_serialization_config(SerializationConfig::default().with_codec(Codec::Json))
+            .build()
+            .unwrap();
+
+        for id in 0..3 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("test{}", id),
+                })
+                .unwrap();
+        }
+
+        assert_eq!(
+            collection.view_as::<IdOnly>().unwrap(),
+            vec![IdOnly { id: 0 }, IdOnly { id: 1 }, IdOnly { id: 2 }]
+        );
+        assert_eq!(collection.find_by_id(1).unwrap().name, "test1");
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let collection = Collection::<MyDocument>::new("test", dir_name);
+
+        assert!(matches!(
+            collection.view_as::<IdOnly>(),
+            Err(CollectionError::CodecNotSelfDescribingError)
+        ));
+    }
+
+    #[test]
+    fn test_peek_ids_on_page_skips_other_fields() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static DECODED: AtomicUsize = AtomicUsize::new(0);
+
+        /// Counts how many times it is decoded.
+        #[derive(Serialize, Clone, Debug, PartialEq)]
+        struct Counted(String);
+
+        impl<'de> serde::Deserialize<'de> for Counted {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                DECODED.fetch_add(1, Ordering::Relaxed);
+                String::deserialize(deserializer).map(Counted)
+            }
+        }
+
+        #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+        struct CountedDocument {
+            id: u64,
+            name: Counted,
+        }
+
+        impl HasId for CountedDocument {
+            type Id = u64;
+
+            fn id(&self) -> u64 {
+                self.id
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<CountedDocument>::builder("test", dir_name)
+            .with_serialization_config(SerializationConfig::default().with_codec(Codec::Json))
+            .build()
+            .unwrap();
+
+        for id in 0..5 {
+            collection
+                .insert_one(&CountedDocument {
+                    id,
+                    name: Counted(format!("test{}", id)),
+                })
+                .unwrap();
+        }
+
+        let decoded = DECODED.load(Ordering::Relaxed);
+        let ids = collection.peek_ids_on_page(0).unwrap();
+
+        assert_eq!(DECODED.load(Ordering::Relaxed), decoded);
+        assert_eq!(
+            ids,
+            collection
+                .collection_file
+                .read_page(0)
+                .unwrap()
+                .documents()
+                .iter()
+                .map(|document| document.id)
+                .collect::<Vec<u64>>()
+        );
+        assert_eq!(ids, vec![0, 1, 2, 3, 4]);
+        assert_eq!(DECODED.load(Ordering::Relaxed), decoded + 5);
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let collection = Collection::<MyDocument>::new("test", dir_name);
+
+        assert!(matches!(
+            collection.peek_ids_on_page(0),
+            Err(CollectionError::CodecNotSelfDescribingError)
+        ));
+    }
+
+    #[test]
+    fn test_retain_keeps_matching_documents() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        // Three documents per page, ten pages.
+        for id in 0..30 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+
+        let before = collection.io_stats();
+        let removed = collection.retain(|document| document.id % 2 == 0).unwrap();
+        let pages_written = collection.io_stats().pages_written - before.pages_written;
+
+        assert_eq!(removed, 15);
+        assert_eq!(pages_written, 10);
+        for id in 0..30 {
+            assert_eq!(collection.find_by_id(id).is_some(), id % 2 == 0);
+        }
+        assert_eq!(collection.find_by(|_| true).unwrap().len(), 15);
+        assert_eq!(
+            collection.retain(|document| document.id % 2 == 0).unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_insert_serialized_reads_back_typed_document() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+        let serialization_config = collection.collection_file.serialization_config();
+
+        let document = MyDocument {
+            id: 1,
+            name: "test1".to_string(),
+        };
+        collection
+            .insert_serialized(1, serialization_config.serialize(&document).unwrap())
+            .unwrap();
+
+        assert_eq!(collection.find_by_id(1), Some(document.clone()));
+        assert_eq!(
+            Collection::<MyDocument>::new("test", dir_name).find_by_id(1),
+            Some(document.clone())
+        );
+        assert!(matches!(
+            collection.insert_serialized(1, serialization_config.serialize(&document).unwrap()),
+            Err(CollectionError::DuplicateError)
+        ));
+        assert!(matches!(
+            collection.insert_serialized(2, serialization_config.serialize(&document).unwrap()),
+            Err(CollectionError::IdMismatchError)
+        ));
+
+        let too_big = MyDocument {
+            id: 3,
+            name: "a".repeat(70_000),
+        };
+        assert!(matches!(
+            collection.insert_serialized(3, serialization_config.serialize(&too_big).unwrap()),
+            Err(CollectionError::DocumentTooBig)
+        ));
+    }
+
+    #[test]
+    fn test_replace_all_swaps_contents() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in 0..10 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "old".repeat(1_000),
+                })
+                .unwrap();
+        }
+
+        let new_documents = (100..105).map(|id| MyDocument {
+            id,
+            name: String::from("new"),
+        });
+        let failed =
+            collection.replace_all(new_documents.clone().chain(std::iter::once(MyDocument {
+                id: 100,
+                name: String::from("duplicate"),
+            })));
+
+        assert!(matches!(failed, Err(CollectionError::DuplicateError)));
+        assert_eq!(collection.find_by(|_| true).unwrap().len(), 10);
+        assert!(!Path::new(&format!("{}/test.collection.tmp", dir_name)).exists());
+
+        collection.replace_all(new_documents.clone()).unwrap();
+
+        assert!(collection.find_by_id(0).is_none());
+        assert_eq!(
+            collection.find_by(|_| true).unwrap(),
+            new_documents.clone().collect::<Vec<_>>()
+        );
+        assert!(!Path::new(&format!("{}/test.collection.tmp", dir_name)).exists());
+
+        collection
+            .insert_one(&MyDocument {
+                id: 1,
+                name: String::from("after"),
+            })
+            .unwrap();
+
+        let reopened = Collection::<MyDocument>::new("test", dir_name);
+
+        assert_eq!(reopened.find_by(|_| true).unwrap().len(), 6);
+        assert_eq!(reopened.find_by_id(104).unwrap().name, "new");
+        assert_eq!(reopened.find_by_id(1).unwrap().name, "after");
+    }
+
+    #[test]
+    fn test_replace_all_discards_the_open_batch() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        collection.begin_batch();
+        collection
+            .insert_one(&MyDocument {
+                id: 1,
+                name: String::from("pending"),
+            })
+            .unwrap();
+        collection
+            .replace_all(std::iter::once(MyDocument {
+                id: 2,
+                name: String::from("new"),
+            }))
+            .unwrap();
+
+        assert!(collection.find_by_id(1).is_none());
+        collection
+            .insert_one(&MyDocument {
+                id: 3,
+                name: String::from("batched"),
+            })
+            .unwrap();
+        collection.commit_batch().unwrap();
+        assert_eq!(collection.find_by(|_| true).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_max_document_size_rejects_documents_fitting_in_a_page() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::builder("test", dir_name)
+            .with_max_document_size(1_000)
+            .build()
+            .unwrap();
+
+        let too_big = MyDocument {
+            id: 1,
+            name: "a".repeat(2_000),
+        };
+
+        assert!(matches!(
+            collection.insert_one(&too_big),
+            Err(CollectionError::DocumentTooBig)
+        ));
+
+        collection
+            .insert_one(&MyDocument {
+                id: 1,
+                name: "a".repeat(500),
+            })
+            .unwrap();
+
+        assert!(matches!(
+            collection.update_one(&too_big),
+            Err(CollectionError::DocumentTooBig)
+        ));
+        assert_eq!(collection.find_by_id(1).unwrap().name, "a".repeat(500));
+    }
+
+    #[test]
+    fn test_page_id_map_lists_ids_per_page() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        // Three documents of 20KB fit on a page, the fourth one starts a new page.
+        for id in 0..5 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+
+        let page_id_map = collection.page_id_map().unwrap();
+
+        assert_eq!(page_id_map.len(), 2);
+        assert_eq!(page_id_map[&0], vec![0, 1, 2]);
+        assert_eq!(page_id_map[&1], vec![3, 4]);
+        for (page_number, ids) in page_id_map.iter() {
+            for id in ids {
+                assert_eq!(collection.id_index().unwrap()[id], *page_number);
+            }
+        }
+    }
+
+    #[test]
+    fn test_insert_many_writes_a_page_once() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        let documents: Vec<MyDocument> = (0..100)
+            .map(|id| MyDocument {
+                id,
+                name: format!("test{}", id),
+            })
+            .collect();
+        let before = collection.io_stats();
+
+        collection.insert_many(&documents).unwrap();
+
+        assert_eq!(
+            collection.io_stats().pages_written - before.pages_written,
+            1
+        );
+        assert_eq!(collection.find_by(|_| true).unwrap(), documents);
+        assert_eq!(collection.find_by_id(42), Some(documents[42].clone()));
+
+        assert!(matches!(
+            collection.insert_many(&documents[..1]),
+            Err(CollectionError::DuplicateError)
+        ));
+    }
+
+    #[test]
+    fn test_find_by_id_checked_heals_stale_index_entry() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in 0..4 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+
+        assert_eq!(collection.id_index().unwrap()[&3], 1);
+        collection.id_to_page_map.get_mut().unwrap().insert(3, 0);
+        collection.id_to_page_map.get_mut().unwrap().insert(7, 1);
+
+        assert!(collection.find_by_id(3).is_none());
+
+        let found = collection.find_by_id_checked(3).unwrap();
+        assert!(found.healed);
+        assert_eq!(found.document.unwrap().id, 3);
+        assert_eq!(collection.id_index().unwrap()[&3], 1);
+
+        let found = collection.find_by_id_checked(3).unwrap();
+        assert!(!found.healed);
+
+        let missing = collection.find_by_id_checked(7).unwrap();
+        assert!(missing.healed);
+        assert!(missing.document.is_none());
+        assert!(!collection.id_index().unwrap().contains_key(&7));
+    }
+
+    #[test]
+    fn test_index_miss_policies() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in 0..4 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+
+        let stale = |index_miss_policy| {
+            let mut collection = Collection::<MyDocument>::new("test", dir_name)
+                .with_index_miss_policy(index_miss_policy);
+            collection.id_to_page_map.get_mut().unwrap().insert(3, 0);
+            collection
+        };
+
+        let collection = stale(IndexMissPolicy::ReturnNone);
+        assert_eq!(collection.try_find_by_id(3).unwrap(), None);
+        assert_eq!(collection.find_by_id(3), None);
+
+        let collection = stale(IndexMissPolicy::Rescan);
+        assert_eq!(collection.try_find_by_id(3).unwrap().unwrap().id, 3);
+        assert_eq!(collection.find_by_id(3).unwrap().id, 3);
+
+        let collection = stale(IndexMissPolicy::Error);
+        assert!(matches!(
+            collection.try_find_by_id(3),
+            Err(CollectionError::StaleIndexError)
+        ));
+        assert_eq!(collection.find_by_id(3), None);
+        assert_eq!(collection.try_find_by_id(2).unwrap().unwrap().id, 2);
+        assert_eq!(collection.try_find_by_id(9).unwrap(), None);
+    }
+
+    #[test]
+    fn test_verify_unique_on_insert_catches_unindexed_ids() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in 0..4 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("test{}", id),
+                })
+                .unwrap();
+        }
+        collection.id_to_page_map.get_mut().unwrap().remove(&2);
+        let duplicate = MyDocument {
+            id: 2,
+            name: "duplicate".to_string(),
+        };
+
+        let mut collection = collection.with_verify_unique_on_insert(true);
+        assert!(matches!(
+            collection.insert_one(&duplicate),
+            Err(CollectionError::DuplicateError)
+        ));
+        assert_eq!(collection.find_by(|doc| doc.id == 2).unwrap().len(), 1);
+        collection
+            .insert_one(&MyDocument {
+                id: 4,
+                name: "test4".to_string(),
+            })
+            .unwrap();
+
+        // Without verification the stale index lets the duplicate in.
+        let mut collection = collection.with_verify_unique_on_insert(false);
+        collection.insert_one(&duplicate).unwrap();
+        assert_eq!(collection.find_by(|doc| doc.id == 2).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_metadata_json_describes_the_collection() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in 0..3 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: String::from("test"),
+                })
+                .unwrap();
+        }
+
+        let metadata = collection.metadata_json();
+        // 8 bytes of id, 8 bytes of length prefix and 4 bytes of name per document.
+        let free_space = collection_page::COLLECTION_PAGE_DATA_SIZE - 3 * 20;
+
+        assert_eq!(metadata["name"], "test");
+        assert_eq!(metadata["page_size"], collection_page::COLLECTION_PAGE_SIZE);
+        assert_eq!(metadata["number_of_pages"], 1);
+        assert_eq!(metadata["number_of_documents"], 3);
+        assert_eq!(metadata["free_space"], free_space);
+        assert_eq!(
+            metadata["fragmentation_ratio"],
+            free_space as f64 / collection_page::COLLECTION_PAGE_DATA_SIZE as f64
+        );
+        assert_eq!(metadata["codec"], "Bincode");
+    }
+
+    #[test]
+    fn test_slot_directory_pages_round_trip() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::builder("test", dir_name)
+            .with_slot_directory()
+            .build()
+            .unwrap();
+
+        for id in 0..100 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("test{}", id),
+                })
+                .unwrap();
+        }
+        collection.delete_one(3).unwrap();
+        collection
+            .update_one(&MyDocument {
+                id: 99,
+                name: String::from("updated"),
+            })
+            .unwrap();
+
+        let reopened = Collection::<MyDocument>::new("test", dir_name);
+
+        assert!(reopened
+            .collection_file
+            .read_page(0)
+            .unwrap()
+            .has_slot_directory());
+        assert!(reopened.find_by_id(3).is_none());
+        assert_eq!(reopened.find_by_id(99).unwrap().name, "updated");
+        assert_eq!(reopened.find_by_id(42).unwrap().name, "test42");
+    }
+
+    #[test]
+    fn test_duplicate_is_independent_from_the_original() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in 0..10 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("test{}", id),
+                })
+                .unwrap();
+        }
+
+        let mut copy = collection.duplicate("copy").unwrap();
+
+        assert_eq!(
+            copy.find_by(|_| true).unwrap(),
+            collection.find_by(|_| true).unwrap()
+        );
+
+        copy.delete_one(0).unwrap();
+        copy.update_one(&MyDocument {
+            id: 1,
+            name: String::from("changed"),
+        })
+        .unwrap();
+        copy.insert_one(&MyDocument {
+            id: 10,
+            name: String::from("new"),
+        })
+        .unwrap();
+
+        let original = Collection::<MyDocument>::new("test", dir_name);
+
+        assert_eq!(original.find_by(|_| true).unwrap().len(), 10);
+        assert_eq!(original.find_by_id(0).unwrap().name, "test0");
+        assert_eq!(original.find_by_id(1).unwrap().name, "test1");
+        assert!(original.find_by_id(10).is_none());
+        assert_eq!(copy.find_by(|_| true).unwrap().len(), 10);
+
+        assert_ne!(
+            copy.collection_file.collection_id(),
+            original.collection_file.collection_id()
+        );
+        let original_page = original.collection_file.read_page(0).unwrap();
+        assert!(matches!(
+            copy.collection_file.write_page(&original_page),
+            Err(CollectionFileError::ForeignPageError)
+        ));
+    }
+
+    #[test]
+    fn test_small_pages_and_page_size_mismatch() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::builder("test", dir_name)
+            .with_page_size(8_192)
+            .build()
+            .unwrap();
+
+        for id in 0..4 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(3_000),
+                })
+                .unwrap();
+        }
+
+        assert_eq!(collection.collection_file.number_of_pages(), 2);
+        assert!(matches!(
+            collection.insert_one(&MyDocument {
+                id: 4,
+                name: "a".repeat(10_000),
+            }),
+            Err(CollectionError::DocumentTooBig)
+        ));
+
+        let mismatch = Collection::<MyDocument>::builder("test", dir_name)
+            .with_page_size(62_000)
+            .build();
+        assert!(matches!(
+            mismatch,
+            Err(CollectionError::FileError(
+                CollectionFileError::PageSizeMismatchError
+            ))
+        ));
+
+        let reopened = Collection::<MyDocument>::new("test", dir_name);
+        assert_eq!(reopened.find_by(|_| true).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_large_pages_take_documents_over_the_default_page_size() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::builder("test", dir_name)
+            .with_page_size(131_072)
+            .build()
+            .unwrap();
+
+        collection
+            .insert_one(&MyDocument {
+                id: 0,
+                name: "a".repeat(100_000),
+            })
+            .unwrap();
+        assert!(matches!(
+            collection.insert_one(&MyDocument {
+                id: 1,
+                name: "a".repeat(140_000),
+            }),
+            Err(CollectionError::DocumentTooBig)
+        ));
+
+        let reopened = Collection::<MyDocument>::new("test", dir_name);
+        assert_eq!(reopened.find_by_id(0).unwrap().name.len(), 100_000);
+    }
+
+    #[test]
+    fn test_scrub_pinpoints_corrupted_document() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in 0..5 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("document{}", id),
+                })
+                .unwrap();
+        }
+        // The second large document lands on a second page, which stays readable.
+        for id in 5..7 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(40_000),
+                })
+                .unwrap();
+        }
+
+        assert!(collection.scrub().unwrap().is_clean());
+
+        // Invalid UTF-8 in the name of the third document of the first page.
+        let path = format!("{}/test.collection", dir_name);
+        let mut bytes = std::fs::read(&path).unwrap();
+        let position = bytes
+            .windows(9)
+            .position(|window| window == b"document2")
+            .unwrap();
+        bytes[position] = 0xFF;
+        std::fs::write(&path, bytes).unwrap();
+
+        let report = collection.scrub().unwrap();
+
+        assert_eq!(report.pages_scanned, 2);
+        assert_eq!(report.documents_scanned, 3);
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].page_number, 0);
+        assert_eq!(report.failures[0].slot, Some(2));
+    }
+
+    #[test]
+    fn test_find_by_fails_on_an_unreadable_page() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        // Three documents per page, document4 on the middle page.
+        for id in 0..7 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("document{}", id).repeat(2_000),
+                })
+                .unwrap();
+        }
+
+        let path = format!("{}/test.collection", dir_name);
+        let mut bytes = std::fs::read(&path).unwrap();
+        let position = bytes
+            .windows(9)
+            .position(|window| window == b"document4")
+            .unwrap();
+        bytes[position] = 0xFF;
+        std::fs::write(&path, bytes).unwrap();
+
+        assert!(collection.find_by(|_| true).is_err());
+    }
+
+    #[test]
+    fn test_read_page_skipping_a_corrupted_document() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in 0..5 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("document{}", id),
+                })
+                .unwrap();
+        }
+
+        // Invalid UTF-8 in the name of the third document.
+        let path = format!("{}/test.collection", dir_name);
+        let mut bytes = std::fs::read(&path).unwrap();
+        let position = bytes
+            .windows(9)
+            .position(|window| window == b"document2")
+            .unwrap();
+        bytes[position] = 0xFF;
+        std::fs::write(&path, bytes).unwrap();
+
+        assert!(collection
+            .collection_file
+            .read_page_with_policy(0, ReadPolicy::Strict)
+            .is_err());
+
+        let (documents, errors) = collection
+            .collection_file
+            .read_page_with_policy(0, ReadPolicy::SkipCorrupt)
+            .unwrap();
+        assert_eq!(
+            documents.iter().map(|doc| doc.id).collect::<Vec<u64>>(),
+            vec![0, 1, 3, 4]
+        );
+        assert_eq!(documents[2].name, "document3");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].index, 2);
+    }
+
+    #[cfg(feature = "json-schema")]
+    #[test]
+    fn test_schema_json_lists_document_properties() {
+        #[derive(Deserialize, Serialize, Clone, Debug, PartialEq, schemars::JsonSchema)]
+        struct SchemaDocument {
+            id: u64,
+            name: String,
+        }
+
+        impl HasId for SchemaDocument {
+            type Id = u64;
+
+            fn id(&self) -> u64 {
+                self.id
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let collection = Collection::<SchemaDocument>::new("test", dir_name);
+
+        let schema = collection.schema_json();
+
+        assert_eq!(schema["type"], "object");
+        assert_eq!(schema["properties"]["id"]["type"], "integer");
+        assert_eq!(schema["properties"]["name"]["type"], "string");
+        assert_eq!(schema["required"], json!(["id", "name"]));
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn test_insert_with_new_uuid() {
+        #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+        struct UuidDocument {
+            id: uuid::Uuid,
+            name: String,
+        }
+
+        impl HasId for UuidDocument {
+            type Id = uuid::Uuid;
+
+            fn id(&self) -> uuid::Uuid {
+                self.id
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<UuidDocument>::new("test", dir_name);
+
+        let first = collection
+            .insert_with_new_uuid(|id| UuidDocument {
+                id,
+                name: String::from("first"),
+            })
+            .unwrap();
+        let second = collection
+            .insert_with_new_uuid(|id| UuidDocument {
+                id,
+                name: String::from("second"),
+            })
+            .unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(collection.find_by_id(first).unwrap().name, "first");
+
+        collection
+            .update_one(&UuidDocument {
+                id: second,
+                name: String::from("updated"),
+            })
+            .unwrap();
+        collection.delete_one(first).unwrap();
+
+        let reopened = Collection::<UuidDocument>::new("test", dir_name);
+        assert!(reopened.find_by_id(first).is_none());
+        assert_eq!(reopened.find_by_id(second).unwrap().name, "updated");
+    }
+
+    #[test]
+    fn test_flush_index_is_loaded_on_open() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::builder("test", dir_name)
+            .with_durability(true)
+            .build()
+            .unwrap();
+
+        for id in 0..10 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("test{}", id),
+                })
+                .unwrap();
+        }
+        collection.flush_index().unwrap();
+
+        let reopened = Collection::<MyDocument>::builder("test", dir_name)
+            .with_persistent_index()
+            .build()
+            .unwrap();
+
+        assert_eq!(reopened.io_stats().pages_read, 0);
+        assert_eq!(reopened.id_index().unwrap().len(), 10);
+        assert_eq!(reopened.find_by_id(7).unwrap().name, "test7");
+
+        // A write after the flush removes the saved index, the next open rescans.
+        collection
+            .insert_one(&MyDocument {
+                id: 10,
+                name: String::from("test10"),
+            })
+            .unwrap();
+
+        let reopened = Collection::<MyDocument>::builder("test", dir_name)
+            .with_persistent_index()
+            .build()
+            .unwrap();
+
+        assert!(reopened.io_stats().pages_read > 0);
+        assert_eq!(reopened.find_by_id(10).unwrap().name, "test10");
+    }
+
+    #[test]
+    fn test_on_commit_receives_every_page_write() {
+        use std::sync::{Arc, Mutex};
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let committed = Arc::new(Mutex::new(Vec::<(u64, Vec<u8>)>::new()));
+        let recorder = committed.clone();
+        let mut collection = Collection::<MyDocument>::builder("test", dir_name)
+            .with_on_commit(Box::new(move |page_number, bytes| {
+                recorder.lock().unwrap().push((page_number, bytes.to_vec()))
+            }))
+            .build()
+            .unwrap();
+
+        for id in 0..5 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("test{}", id),
+                })
+                .unwrap();
+        }
+
+        let committed = committed.lock().unwrap();
+        assert_eq!(committed.len(), 5);
+
+        let (page_number, bytes) = committed.last().unwrap();
+        let page = bincode::deserialize::<CollectionPage<MyDocument>>(bytes).unwrap();
+        assert_eq!(*page_number, 0);
+        assert_eq!(page, collection.collection_file.read_page(0).unwrap());
+        assert_eq!(page.documents().len(), 5);
+    }
+
+    #[test]
+    fn test_find_by_using_index_reads_only_matching_pages() {
+        fn group(document: &MyDocument) -> u64 {
+            document.id % 10
+        }
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut indexed = Collection::<MyDocument>::new("indexed", dir_name)
+            .with_secondary_index("group", group)
+            .unwrap();
+        let mut scanned =
+            Collection::<MyDocument>::new("scanned", dir_name).with_index_key("group", group);
+
+        // Three documents per page, ten pages.
+        for id in 0..30 {
+            let document = MyDocument {
+                id,
+                name: "a".repeat(20_000),
+            };
+            indexed.insert_one(&document).unwrap();
+            scanned.insert_one(&document).unwrap();
+        }
+
+        let before = indexed.io_stats();
+        let found = indexed.find_by_using_index("group", 0u64).unwrap();
+        let indexed_reads = indexed.io_stats().pages_read - before.pages_read;
+
+        let before = scanned.io_stats();
+        let expected = scanned.find_by_using_index("group", 0u64).unwrap();
+        let scanned_reads = scanned.io_stats().pages_read - before.pages_read;
+
+        assert_eq!(found, expected);
+        assert_eq!(
+            found
+                .iter()
+                .map(|document| document.id)
+                .collect::<Vec<u64>>(),
+            vec![0, 10, 20]
+        );
+        assert_eq!(indexed_reads, 3);
+        assert_eq!(scanned_reads, 10);
+        assert!(matches!(
+            indexed.find_by_using_index("missing", 0u64),
+            Err(CollectionError::NotFoundError)
+        ));
+    }
+
+    #[test]
+    fn test_range_reads_only_pages_in_range() {
+        fn id(document: &MyDocument) -> u64 {
+            document.id
+        }
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name)
+            .with_ordered_index("id", id)
+            .unwrap();
+
+        // Three documents per page, ten pages.
+        for id in 0..30 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+
+        let before = collection.io_stats();
+        let found = collection.range("id", 4u64..10).unwrap();
+        let reads = collection.io_stats().pages_read - before.pages_read;
+
+        assert_eq!(
+            found
+                .iter()
+                .map(|document| document.id)
+                .collect::<Vec<u64>>(),
+            vec![4, 5, 6, 7, 8, 9]
+        );
+        assert_eq!(reads, 3);
+        assert_eq!(collection.range("id", 25u64..).unwrap().len(), 5);
+        assert!(matches!(
+            collection.range("id", 0u32..10),
+            Err(CollectionError::NotFoundError)
+        ));
+    }
+
+    #[test]
+    fn test_content_hash_ignores_physical_layout() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for document in gen_documents(200, 2) {
+            collection.insert_one(&document).unwrap();
+        }
+        for id in (0..200).step_by(3) {
+            collection.delete_one(id).unwrap();
+        }
+
+        let mut compacted = collection.duplicate("compacted").unwrap();
+        let mut documents = collection.find_by(|_| true).unwrap();
+        documents.reverse();
+        compacted.replace_all(documents.into_iter()).unwrap();
+
+        assert!(
+            compacted.collection_file.number_of_pages()
+                < collection.collection_file.number_of_pages()
+        );
+        assert_eq!(
+            compacted.content_hash().unwrap(),
+            collection.content_hash().unwrap()
+        );
+
+        compacted
+            .update_one(&MyDocument {
+                id: 1,
+                name: "b".to_string(),
+            })
+            .unwrap();
+        assert_ne!(
+            compacted.content_hash().unwrap(),
+            collection.content_hash().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_without_first_page_creates_page_0_on_insert() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::builder("test", dir_name)
+            .without_first_page()
+            .build()
+            .unwrap();
+
+        assert_eq!(collection.collection_file.number_of_pages(), 0);
+        assert_eq!(collection.find_by(|_| true).unwrap(), vec![]);
+
+        let document = MyDocument {
+            id: 1,
+            name: "a".to_string(),
+        };
+        collection.insert_one(&document).unwrap();
+
+        assert_eq!(collection.collection_file.number_of_pages(), 1);
+        assert_eq!(
+            collection.collection_file.read_page(0).unwrap().documents(),
+            &vec![document.clone()]
+        );
+        assert_eq!(collection.find_by_id(1), Some(document));
+    }
+
+    #[test]
+    fn test_find_by_each_stops_when_sink_returns_false() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        // Three documents per page, ten pages.
+        for id in 0..30 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+
+        let mut found = vec![];
+        let before = collection.io_stats();
+        collection
+            .find_by_each(
+                |document| document.id % 2 == 1,
+                |document| {
+                    found.push(document.id);
+                    found.len() < 3
+                },
+            )
+            .unwrap();
+        let pages_read = collection.io_stats().pages_read - before.pages_read;
+
+        assert_eq!(found, vec![1, 3, 5]);
+        assert_eq!(pages_read, 2);
+    }
+
+    #[test]
+    fn test_resize_pages_keeps_documents() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in 0..20 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(1_000),
+                })
+                .unwrap();
+        }
+        assert_eq!(collection.collection_file.number_of_pages(), 1);
+
+        collection.resize_pages(4_096).unwrap();
+
+        assert_eq!(collection.collection_file.page_size(), 4_096);
+        assert!(collection.collection_file.number_of_pages() > 1);
+        for id in 0..20 {
+            assert_eq!(collection.find_by_id(id).unwrap().name, "a".repeat(1_000));
+        }
+
+        let reopened = Collection::<MyDocument>::new("test", dir_name);
+        assert_eq!(reopened.collection_file.page_size(), 4_096);
+        assert_eq!(reopened.find_by(|_| true).unwrap().len(), 20);
+
+        assert!(matches!(
+            collection.resize_pages(512),
+            Err(CollectionError::DocumentTooBig)
+        ));
+        assert_eq!(collection.collection_file.page_size(), 4_096);
+        assert_eq!(collection.find_by(|_| true).unwrap().len(), 20);
+    }
+
+    #[test]
+    fn test_lazy_index_is_built_on_first_lookup() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in 0..10 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+
+        let mut lazy = Collection::<MyDocument>::builder("test", dir_name)
+            .with_lazy_index()
+            .build()
+            .unwrap();
+
+        assert_eq!(lazy.io_stats().pages_read, 0);
+        assert!(lazy.id_to_page_map.get().is_none());
+
+        assert_eq!(lazy.find_by_id(7).unwrap().name, "a".repeat(20_000));
+        // Four pages scanned to build the index, then the page holding the document.
+        assert_eq!(lazy.io_stats().pages_read, 5);
+        assert_eq!(lazy.id_index().unwrap().len(), 10);
+
+        lazy.find_by_id(8).unwrap();
+        assert_eq!(lazy.io_stats().pages_read, 6);
+        assert!(matches!(
+            lazy.insert_one(&MyDocument {
+                id: 3,
+                name: "b".to_string(),
+            }),
+            Err(CollectionError::DuplicateError)
+        ));
+    }
+
+    #[test]
+    fn test_index_build_plan_lists_the_data_pages() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        // Three documents per page.
+        for id in 0..15 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+
+        let lazy = Collection::<MyDocument>::builder("test", dir_name)
+            .with_lazy_index()
+            .build()
+            .unwrap();
+        let plan = lazy.index_build_plan();
+        assert_eq!(plan, vec![0, 1, 2, 3, 4]);
+
+        lazy.id_index().unwrap();
+        assert_eq!(lazy.io_stats().pages_read, plan.len() as u64);
+    }
+
+    #[test]
+    fn test_iter_sorted_merges_pages_in_id_order() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        // Ids spread over several pages with no order between or within them.
+        for i in 0..20u64 {
+            collection
+                .insert_one(&MyDocument {
+                    id: (i * 7) % 20,
+                    name: "a".repeat(10_000),
+                })
+                .unwrap();
+        }
+        collection.begin_batch();
+        collection
+            .insert_one(&MyDocument {
+                id: 25,
+                name: "pending".to_string(),
+            })
+            .unwrap();
+
+        assert!(collection.collection_file.number_of_pages() > 1);
+        assert_eq!(
+            collection
+                .iter_sorted()
+                .unwrap()
+                .map(|document| document.id)
+                .collect::<Vec<u64>>(),
+            (0..20).chain(Some(25)).collect::<Vec<u64>>()
+        );
+    }
+
+    #[test]
+    fn test_large_documents_are_listed_by_size() {
         let dir = tempdir().unwrap();
         let binding = dir.into_path();
         let dir_name = binding.to_str().unwrap();
         let mut collection = Collection::<MyDocument>::new("test", dir_name);
 
-        let documents: Vec<MyDocument> = vec![
-            MyDocument {
-                id: 0,
-                name: String::from("test1"),
-            },
-            MyDocument {
-                id: 1,
-                name: String::from("test2"),
-            },
-        ];
+        for (id, name_length) in [(0, 10), (1, 3_000), (2, 50), (3, 20_000), (4, 1_000)] {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(name_length),
+                })
+                .unwrap();
+        }
 
-        for document in &documents {
-            collection.insert_one(&document).unwrap();
+        // A document is its 8 bytes id, then the 8 bytes length and bytes of its name.
+        assert_eq!(
+            collection.large_documents(1_016).unwrap(),
+            vec![(3, 20_016), (1, 3_016)]
+        );
+        assert_eq!(collection.large_documents(1_000).unwrap().len(), 3);
+        assert!(collection.large_documents(20_016).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sample_is_deterministic_for_a_seed() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        // Three documents per page.
+        for id in 0..9 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
         }
 
-        let doc_from_collection = collection.find_by(|_| true);
+        let before = collection.io_stats();
+        let sample = collection.sample(4, 42).unwrap();
+        assert!(collection.io_stats().pages_read - before.pages_read <= 3);
 
-        assert_eq!(documents, doc_from_collection);
+        let mut ids: Vec<u64> = sample.iter().map(|doc| doc.id).collect();
+        ids.sort();
+        ids.dedup();
+        assert_eq!(ids.len(), 4);
+
+        // A reopened collection rebuilds its index in another order.
+        drop(collection);
+        let reopened = Collection::<MyDocument>::new("test", dir_name);
+        assert_eq!(reopened.sample(4, 42).unwrap(), sample);
+        assert_eq!(reopened.sample(20, 42).unwrap().len(), 9);
+        assert!(reopened.sample(0, 42).unwrap().is_empty());
     }
 
     #[test]
-    fn test_insert_find_by_collection() {
+    fn test_diff_buckets_added_removed_and_changed_documents() {
         let dir = tempdir().unwrap();
         let binding = dir.into_path();
         let dir_name = binding.to_str().unwrap();
-        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+        let mut left = Collection::<MyDocument>::new("left", dir_name);
+        let mut right = Collection::<MyDocument>::new("right", dir_name);
 
-        let documents: Vec<MyDocument> = vec![
-            MyDocument {
-                id: 0,
-                name: String::from("test1"),
-            },
-            MyDocument {
-                id: 1,
-                name: String::from("test2"),
-            },
-            MyDocument {
-                id: 2,
-                name: String::from("test3"),
-            },
-            MyDocument {
+        for id in 0..6 {
+            let document = MyDocument {
+                id,
+                name: format!("test{}", id),
+            };
+            left.insert_one(&document).unwrap();
+            right.insert_one(&document).unwrap();
+        }
+        left.insert_one(&MyDocument {
+            id: 6,
+            name: "left".to_string(),
+        })
+        .unwrap();
+        right
+            .insert_one(&MyDocument {
+                id: 7,
+                name: "right".to_string(),
+            })
+            .unwrap();
+        right.delete_one(0).unwrap();
+        right
+            .update_one(&MyDocument {
                 id: 3,
-                name: String::from("test4"),
-            },
-        ];
+                name: "changed".to_string(),
+            })
+            .unwrap();
 
-        for document in &documents {
-            collection.insert_one(&document).unwrap();
+        let mut diff = left.diff(&right).unwrap();
+        diff.only_in_self.sort();
+        diff.only_in_other.sort();
+        diff.changed.sort();
+
+        assert_eq!(diff.only_in_self, vec![0, 6]);
+        assert_eq!(diff.only_in_other, vec![7]);
+        assert_eq!(diff.changed, vec![3]);
+
+        let same = left.diff(&left).unwrap();
+        assert!(same.only_in_self.is_empty() && same.changed.is_empty());
+    }
+
+    #[test]
+    fn test_write_back_persists_on_flush() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::builder("test", dir_name)
+            .with_write_back()
+            .build()
+            .unwrap();
+        let before = collection.io_stats();
+
+        // Three documents per page.
+        let documents: Vec<MyDocument> = (0..8)
+            .map(|id| MyDocument {
+                id,
+                name: "a".repeat(20_000),
+            })
+            .collect();
+        collection.insert_many(&documents).unwrap();
+        collection
+            .insert_one(&MyDocument {
+                id: 8,
+                name: "test8".to_string(),
+            })
+            .unwrap();
+        collection
+            .update_one(&MyDocument {
+                id: 2,
+                name: "updated".to_string(),
+            })
+            .unwrap();
+        collection
+            .upsert_one(&MyDocument {
+                id: 9,
+                name: "test9".to_string(),
+            })
+            .unwrap();
+        collection.delete_one(5).unwrap();
+        assert_eq!(collection.retain(|doc| doc.id != 7).unwrap(), 1);
+
+        assert_eq!(collection.find_by_id(2).unwrap().name, "updated");
+        assert!(collection.find_by_id(5).is_none());
+        assert_eq!(collection.find_by(|_| true).unwrap().len(), 8);
+
+        // Nothing reached the file yet, the flush writes every page at once.
+        let stats = collection.io_stats();
+        assert_eq!(stats.pages_read, before.pages_read);
+        assert_eq!(stats.pages_written, before.pages_written);
+        let number_of_pages = collection.collection_file.number_of_pages();
+        collection.flush().unwrap();
+        assert_eq!(
+            collection.io_stats().pages_written - stats.pages_written,
+            number_of_pages
+        );
+        collection.flush().unwrap();
+        assert_eq!(
+            collection.io_stats().pages_written - stats.pages_written,
+            number_of_pages
+        );
+
+        let reopened = Collection::<MyDocument>::new("test", dir_name);
+        let mut ids: Vec<u64> = reopened
+            .find_by(|_| true)
+            .unwrap()
+            .iter()
+            .map(|doc| doc.id)
+            .collect();
+        ids.sort();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4, 6, 8, 9]);
+        assert_eq!(reopened.find_by_id(2).unwrap().name, "updated");
+
+        // Dropping the collection flushes the last changes.
+        collection.delete_one(0).unwrap();
+        drop(collection);
+        let reopened = Collection::<MyDocument>::new("test", dir_name);
+        assert!(reopened.find_by_id(0).is_none());
+        assert_eq!(reopened.find_by(|_| true).unwrap().len(), 7);
+    }
+
+    #[test]
+    fn test_insert_into_page_uses_the_given_page() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        // Three documents per page, page 0 still has room for a small document.
+        for id in 0..4 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+
+        collection
+            .insert_into_page(
+                1,
+                &MyDocument {
+                    id: 10,
+                    name: "test10".to_string(),
+                },
+            )
+            .unwrap();
+
+        assert_eq!(collection.id_index().unwrap().get(&10), Some(&1));
+        assert_eq!(collection.find_by_id(10).unwrap().name, "test10");
+        assert!(collection
+            .collection_file
+            .read_page(1)
+            .unwrap()
+            .find_document(10)
+            .is_some());
+
+        assert!(matches!(
+            collection.insert_into_page(
+                0,
+                &MyDocument {
+                    id: 11,
+                    name: "a".repeat(20_000),
+                },
+            ),
+            Err(CollectionError::PageError(
+                CollectionPageError::NoFreeSpaceAvailable
+            ))
+        ));
+        assert!(matches!(
+            collection.insert_into_page(
+                1,
+                &MyDocument {
+                    id: 0,
+                    name: "test0".to_string(),
+                },
+            ),
+            Err(CollectionError::DuplicateError)
+        ));
+        assert!(collection.find_by_id(11).is_none());
+    }
+
+    #[test]
+    fn test_find_all_ordered_ignores_physical_layout() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in [3, 0, 1, 4, 2, 5] {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("test{}", id),
+                })
+                .unwrap();
         }
+        // The last document of the page moves into the slot of the removed one.
+        collection.delete_one(1).unwrap();
+
+        let physical: Vec<u64> = collection
+            .find_by(|_| true)
+            .unwrap()
+            .iter()
+            .map(|doc| doc.id)
+            .collect();
+        assert_eq!(physical, vec![3, 0, 5, 4, 2]);
 
-        let doc_from_collection = collection.find_by(|doc| doc.id() % 2 == 0);
+        let ordered: Vec<u64> = collection
+            .find_all_ordered()
+            .unwrap()
+            .iter()
+            .map(|doc| doc.id)
+            .collect();
+        assert_eq!(ordered, vec![0, 2, 3, 4, 5]);
 
+        drop(collection);
+        let reopened = Collection::<MyDocument>::new("test", dir_name);
         assert_eq!(
-            vec![
+            reopened
+                .find_all_ordered()
+                .unwrap()
+                .iter()
+                .map(|doc| doc.id)
+                .collect::<Vec<u64>>(),
+            ordered
+        );
+    }
+
+    #[test]
+    fn test_find_all_ordered_uses_the_default_order() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for (id, name) in [(0, "b"), (1, "c"), (2, "a"), (3, "c")] {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: name.to_string(),
+                })
+                .unwrap();
+        }
+
+        collection.set_default_order(|document| document.name.clone(), true);
+
+        let ordered: Vec<u64> = collection
+            .find_all_ordered()
+            .unwrap()
+            .iter()
+            .map(|doc| doc.id)
+            .collect();
+        assert_eq!(ordered, vec![1, 3, 0, 2]);
+    }
+
+    #[test]
+    fn test_current_append_page_advances_when_a_page_fills() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        // Three documents per page.
+        let mut append_pages = vec![];
+        for id in 0..7 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+            append_pages.push(collection.current_append_page());
+        }
+
+        assert_eq!(append_pages, vec![0, 0, 0, 1, 1, 1, 2]);
+
+        collection
+            .insert_many(&[
                 MyDocument {
-                    id: 0,
-                    name: String::from("test1"),
+                    id: 7,
+                    name: "a".repeat(20_000),
                 },
                 MyDocument {
+                    id: 8,
+                    name: "a".repeat(20_000),
+                },
+                MyDocument {
+                    id: 9,
+                    name: "a".repeat(20_000),
+                },
+            ])
+            .unwrap();
+        assert_eq!(collection.current_append_page(), 3);
+    }
+
+    #[test]
+    fn test_delete_by_index_writes_only_matching_pages() {
+        fn status(document: &MyDocument) -> u64 {
+            document.id % 10
+        }
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name)
+            .with_secondary_index("status", status)
+            .unwrap();
+
+        // Three documents per page, ten pages.
+        for id in 0..30 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+
+        let before = collection.io_stats();
+        let removed = collection.delete_by_index("status", 4u64).unwrap();
+        let after = collection.io_stats();
+
+        assert_eq!(removed, 3);
+        assert_eq!(after.pages_read - before.pages_read, 3);
+        assert_eq!(after.pages_written - before.pages_written, 3);
+        for id in 0..30 {
+            assert_eq!(collection.find_by_id(id).is_some(), id % 10 != 4);
+        }
+        assert!(collection
+            .find_by_using_index("status", 4u64)
+            .unwrap()
+            .is_empty());
+        assert_eq!(collection.delete_by_index("status", 4u64).unwrap(), 0);
+        assert_eq!(
+            collection
+                .find_by_using_index("status", 5u64)
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn test_find_duplicate_ids_across_pages() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in 0..3 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("test{}", id),
+                })
+                .unwrap();
+        }
+        assert!(collection.find_duplicate_ids().unwrap().is_empty());
+
+        // Bypasses the index to store id 1 a second time on a new page.
+        let mut page = collection.collection_file.new_page(1);
+        page.insert_document(&MyDocument {
+            id: 1,
+            name: String::from("copy"),
+        })
+        .unwrap();
+        collection.collection_file.write_page(&page).unwrap();
+
+        assert_eq!(
+            collection.find_duplicate_ids().unwrap(),
+            vec![(1, vec![0, 1])]
+        );
+    }
+
+    #[test]
+    fn test_fill_factor_keeps_room_for_updates() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::builder("test", dir_name)
+            .with_fill_factor(0.8)
+            .build()
+            .unwrap();
+
+        for id in 0..100 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(1_000),
+                })
+                .unwrap();
+        }
+
+        let data_size = collection.collection_file.page_data_size() as f64;
+        let header = collection.collection_file.read_page_header(0).unwrap();
+        let used = data_size - header.space_available() as f64;
+
+        assert!(used <= 0.8 * data_size);
+        assert!(used + 1_016.0 > 0.8 * data_size);
+
+        let number_of_pages = collection.collection_file.number_of_pages();
+        collection
+            .update_one(&MyDocument {
+                id: 0,
+                name: "a".repeat(2_000),
+            })
+            .unwrap();
+
+        assert_eq!(collection.id_index().unwrap()[&0], 0);
+        assert_eq!(
+            collection.collection_file.number_of_pages(),
+            number_of_pages
+        );
+        assert_eq!(collection.find_by_id(0).unwrap().name.len(), 2_000);
+    }
+
+    #[test]
+    fn test_export_and_import_index() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in 0..10 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+
+        let mut exported = vec![];
+        collection.export_index(&mut exported).unwrap();
+
+        // Entry count, then 10 entries of a length, an 8 bytes id and a page number.
+        assert_eq!(exported.len(), 8 + 10 * (4 + 8 + 8));
+
+        let imported = Collection::<MyDocument>::import_index(&exported[..]).unwrap();
+
+        assert_eq!(imported, *collection.id_index().unwrap());
+        assert_eq!(imported[&9], 3);
+    }
+
+    #[test]
+    fn test_sorted_index_matches_the_id_index() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in (0..10).rev() {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+        collection.begin_batch();
+        collection
+            .insert_one(&MyDocument {
+                id: 10,
+                name: "pending".to_string(),
+            })
+            .unwrap();
+
+        let sorted_index = collection.build_sorted_index(4).unwrap();
+
+        assert_eq!(sorted_index.len(), 10);
+        for (id, page_number) in collection.id_index().unwrap().iter() {
+            assert_eq!(sorted_index.get(id).unwrap(), Some(*page_number));
+        }
+        assert_eq!(sorted_index.get(&10).unwrap(), None);
+    }
+
+    #[test]
+    fn test_export_filtered_writes_projected_ndjson() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        // Three documents per page.
+        for id in 0..7 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("test{}", id).repeat(4_000),
+                })
+                .unwrap();
+        }
+
+        let mut exported = vec![];
+        let written = collection
+            .export_filtered(
+                |document| document.id % 2 == 0,
+                |document| json!({"id": document.id, "name": &document.name[..5]}),
+                &mut exported,
+            )
+            .unwrap();
+
+        assert_eq!(written, 4);
+        assert_eq!(
+            String::from_utf8(exported).unwrap(),
+            "{\"id\":0,\"name\":\"test0\"}\n\
+             {\"id\":2,\"name\":\"test2\"}\n\
+             {\"id\":4,\"name\":\"test4\"}\n\
+             {\"id\":6,\"name\":\"test6\"}\n"
+        );
+    }
+
+    #[test]
+    fn test_update_if_applies_only_when_predicate_holds() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        collection
+            .insert_one(&MyDocument {
+                id: 1,
+                name: String::from("pending"),
+            })
+            .unwrap();
+
+        let updated = collection
+            .update_if(
+                &MyDocument {
+                    id: 1,
+                    name: String::from("done"),
+                },
+                |current| current.name == "pending",
+            )
+            .unwrap();
+
+        assert!(updated);
+        assert_eq!(collection.find_by_id(1).unwrap().name, "done");
+
+        let updated = collection
+            .update_if(
+                &MyDocument {
+                    id: 1,
+                    name: String::from("cancelled"),
+                },
+                |current| current.name == "pending",
+            )
+            .unwrap();
+
+        assert!(!updated);
+        assert_eq!(collection.find_by_id(1).unwrap().name, "done");
+        assert!(matches!(
+            collection.update_if(
+                &MyDocument {
                     id: 2,
-                    name: String::from("test3"),
+                    name: String::from("missing"),
                 },
-            ],
-            doc_from_collection
+                |_| true
+            ),
+            Err(CollectionError::NotFoundError)
+        ));
+    }
+
+    #[test]
+    fn test_documents_from_page_returns_newer_pages() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+        let documents: Vec<MyDocument> = (0..6)
+            .map(|id| MyDocument {
+                id,
+                name: "a".repeat(20_000),
+            })
+            .collect();
+
+        for document in documents[..3].iter() {
+            collection.insert_one(document).unwrap();
+        }
+        let recorded_number_of_pages = collection.collection_file.number_of_pages();
+
+        for document in documents[3..].iter() {
+            collection.insert_one(document).unwrap();
+        }
+
+        assert_eq!(
+            collection
+                .documents_from_page(recorded_number_of_pages)
+                .unwrap(),
+            documents[3..].to_vec()
+        );
+        assert_eq!(collection.documents_from_page(0).unwrap(), documents);
+        assert!(collection.documents_from_page(10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_for_each_page_mut_backfills_a_field() {
+        #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+        struct TaggedDocument {
+            id: u64,
+            payload: String,
+            tag: Option<String>,
+        }
+
+        impl HasId for TaggedDocument {
+            type Id = u64;
+
+            fn id(&self) -> u64 {
+                self.id
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<TaggedDocument>::new("test", dir_name);
+
+        for id in 0..7 {
+            collection
+                .insert_one(&TaggedDocument {
+                    id,
+                    payload: "a".repeat(20_000),
+                    tag: if id == 0 {
+                        Some(String::from("kept"))
+                    } else {
+                        None
+                    },
+                })
+                .unwrap();
+        }
+
+        let before = collection.io_stats();
+        collection
+            .for_each_page_mut(|page| {
+                let mut modified = false;
+                for document in page.documents_mut().iter_mut() {
+                    if document.tag.is_none() {
+                        document.tag = Some(String::from("default"));
+                        modified = true;
+                    }
+                }
+                modified
+            })
+            .unwrap();
+
+        assert_eq!(
+            collection.io_stats().pages_written - before.pages_written,
+            collection.collection_file.number_of_pages()
         );
+
+        let reopened = Collection::<TaggedDocument>::new("test", dir_name);
+        assert_eq!(reopened.find_by_id(0).unwrap().tag.unwrap(), "kept");
+        for id in 1..7 {
+            assert_eq!(reopened.find_by_id(id).unwrap().tag.unwrap(), "default");
+        }
     }
 }
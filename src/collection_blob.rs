@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::os::unix::prelude::FileExt;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collection_file::CollectionFileError;
+
+pub const BLOB_PAGE_SIZE: u64 = 4_096;
+
+/// Written at the start of the first overflow page of every blob.
+#[derive(Serialize, Deserialize, Debug)]
+struct BlobHeader {
+    key: Vec<u8>,
+    length: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BlobLocation {
+    data_offset: u64,
+    length: u64,
+}
+
+/// Binary blobs stored outside of the document pages, in a sidecar file of overflow pages.
+/// A blob takes a contiguous run of pages appended at the end of the file. Putting a blob
+/// again under the same key appends a new run, the latest one wins when the file is reopened.
+#[derive(Debug)]
+pub struct BlobStore {
+    file: File,
+    number_of_pages: u64,
+    directory: HashMap<Vec<u8>, BlobLocation>,
+}
+
+impl BlobStore {
+    pub fn open(name: &str, dir: &str) -> Result<Self, CollectionFileError> {
+        let binding = format!("{}/{}.blob", dir, name);
+        let path = Path::new(&binding);
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .read(true)
+            .open(path)?;
+
+        let file_size = file.metadata()?.len();
+        let mut directory = HashMap::new();
+        let mut page_number = 0;
+
+        while page_number * BLOB_PAGE_SIZE < file_size {
+            let offset = page_number * BLOB_PAGE_SIZE;
+            let mut encoded = vec![0u8; BLOB_PAGE_SIZE as usize];
+            file.read_at(&mut encoded, offset)?;
+
+            let header = bincode::deserialize::<BlobHeader>(&encoded[..])?;
+            let header_size = bincode::serialized_size(&header)?;
+
+            directory.insert(
+                header.key,
+                BlobLocation {
+                    data_offset: offset + header_size,
+                    length: header.length,
+                },
+            );
+            page_number += Self::pages_needed(header_size + header.length);
+        }
+
+        Ok(BlobStore {
+            file,
+            number_of_pages: page_number,
+            directory,
+        })
+    }
+
+    fn pages_needed(size: u64) -> u64 {
+        size.div_ceil(BLOB_PAGE_SIZE).max(1)
+    }
+
+    pub fn put(&mut self, key: Vec<u8>, blob: &[u8]) -> Result<(), CollectionFileError> {
+        let header = BlobHeader {
+            key,
+            length: blob.len() as u64,
+        };
+        let mut encoded = bincode::serialize(&header)?;
+        let header_size = encoded.len() as u64;
+        encoded.extend_from_slice(blob);
+
+        let offset = self.number_of_pages * BLOB_PAGE_SIZE;
+        self.file.write_all_at(&encoded, offset)?;
+
+        self.number_of_pages += Self::pages_needed(encoded.len() as u64);
+        self.directory.insert(
+            header.key,
+            BlobLocation {
+                data_offset: offset + header_size,
+                length: header.length,
+            },
+        );
+
+        Ok(())
+    }
+
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, CollectionFileError> {
+        let location = match self.directory.get(key) {
+            Some(location) => location,
+            None => return Ok(None),
+        };
+
+        let mut blob = vec![0u8; location.length as usize];
+        self.file.read_exact_at(&mut blob, location.data_offset)?;
+
+        Ok(Some(blob))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_put_and_get_blobs_across_reopen() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut blob_store = BlobStore::open("test", dir_name).unwrap();
+
+        let large_blob: Vec<u8> = (0..10_000).map(|i| (i % 251) as u8).collect();
+        blob_store.put(vec![1], &large_blob).unwrap();
+        blob_store.put(vec![2], &[]).unwrap();
+        blob_store.put(vec![3], b"first").unwrap();
+        blob_store.put(vec![3], b"second").unwrap();
+
+        let reopened = BlobStore::open("test", dir_name).unwrap();
+
+        assert_eq!(reopened.get(&[1]).unwrap(), Some(large_blob));
+        assert_eq!(reopened.get(&[2]).unwrap(), Some(vec![]));
+        assert_eq!(reopened.get(&[3]).unwrap(), Some(b"second".to_vec()));
+        assert_eq!(reopened.get(&[4]).unwrap(), None);
+    }
+}
@@ -1,21 +1,113 @@
 use crate::collection_page::{CollectionPage, CollectionPageHeader, COLLECTION_PAGE_SIZE};
+use crate::collection_scrub::{salvage_page, DocError};
+use crate::collection_superblock::{CollectionSuperblock, IdTypeTag, SUPERBLOCK_SIZE};
 use crate::document::Document;
+use crate::serialization::SerializationConfig;
+use crate::storage::Storage;
 use bincode::ErrorKind;
-use std::fs::{File, OpenOptions};
+use serde::de::{DeserializeOwned, IgnoredAny};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
 use std::marker::PhantomData;
-use std::os::unix::prelude::FileExt;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
 
 #[derive(Debug)]
 pub struct CollectionFile<T: Document> {
+    name: String,
+    dir: String,
     number_of_pages: u64,
-    file: File,
+    file: Box<dyn Storage>,
+    collection_id: u64,
+    serialization_config: SerializationConfig,
+    page_size: u64,
+    pages_read: AtomicU64,
+    headers_read: AtomicU64,
+    pages_written: AtomicU64,
+    cache_hits: AtomicU64,
+    page_cache: Option<PageCache>,
+    write_back: Option<WriteBack<T>>,
+    slot_directory: bool,
+    index_sidecar_armed: AtomicBool,
+    on_commit: Option<CommitHook>,
     _marker: PhantomData<T>,
 }
 
+pub type OnCommit = Box<dyn Fn(u64, &[u8]) + Send + Sync>;
+
+/// Called with the page number and the bytes of every page once its write is durable.
+pub struct CommitHook(pub OnCommit);
+
+impl std::fmt::Debug for CommitHook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CommitHook")
+    }
+}
+
+/// Encoded bytes of up to `capacity` pages, a full cache evicts an arbitrary page.
+#[derive(Debug)]
+struct PageCache {
+    capacity: usize,
+    pages: Mutex<HashMap<u64, Vec<u8>>>,
+}
+
+/// Every page of the file held in memory, written back to the file by `flush`.
+#[derive(Debug)]
+struct WriteBack<T> {
+    pages: Vec<CollectionPage<T>>,
+    dirty: AtomicBool,
+}
+
+/// Number of page reads, page header reads and page writes done through a collection file,
+/// and of page reads served by the page cache instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IoStats {
+    pub pages_read: u64,
+    pub headers_read: u64,
+    pub pages_written: u64,
+    pub cache_hits: u64,
+}
+
+/// How `read_page_with_policy` handles documents that can't be decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadPolicy {
+    /// The whole read fails, like `read_page`.
+    Strict,
+    /// The document is reported and the other documents of the page are returned.
+    SkipCorrupt,
+}
+
+/// A document decoded up to its `id` field, the other fields are skipped.
+#[derive(Deserialize)]
+struct DocumentId<I> {
+    id: I,
+}
+
+/// CRC-32 (IEEE), computed bit by bit as checksums are only taken on demand.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg());
+        }
+    }
+
+    !crc
+}
+
 #[derive(Debug)]
 pub enum CollectionFileError {
     PageNumberTooHighError,
+    ForeignPageError,
+    InvalidSuperblockError,
+    SerializationConfigMismatchError,
+    PageSizeMismatchError,
+    IdTypeMismatchError,
+    PageNumberMismatchError { position: u64, recorded: u64 },
+    IdSequenceOverflowError,
     FileError(std::io::Error),
     SerializationError(Box<ErrorKind>),
 }
@@ -34,6 +126,29 @@ impl From<Box<ErrorKind>> for CollectionFileError {
 
 impl<T: Document> CollectionFile<T> {
     pub fn new(name: &str, dir: &str) -> Result<Self, CollectionFileError> {
+        Self::open(name, dir, SerializationConfig::default())
+    }
+
+    /// Opens the collection file, creating it with the given serialization config if needed.
+    /// An existing file must have been created with the same config.
+    pub fn open(
+        name: &str,
+        dir: &str,
+        serialization_config: SerializationConfig,
+    ) -> Result<Self, CollectionFileError> {
+        Self::open_with_page_size(name, dir, serialization_config, None, true)
+    }
+
+    /// Like `open`, a new file is created with `page_size` bytes per page. For an existing file
+    /// `None` uses the stored page size and any other size must match it. A file without pages
+    /// gets an empty page 0 only when `create_first_page` is set.
+    pub fn open_with_page_size(
+        name: &str,
+        dir: &str,
+        serialization_config: SerializationConfig,
+        page_size: Option<u64>,
+        create_first_page: bool,
+    ) -> Result<Self, CollectionFileError> {
         let binding = format!("{}/{}.collection", dir, name);
         let path = Path::new(&binding);
         let file = OpenOptions::new()
@@ -41,10 +156,71 @@ impl<T: Document> CollectionFile<T> {
             .write(true)
             .read(true)
             .open(&path)?;
+
+        Self::from_storage(
+            name,
+            dir,
+            Box::new(file),
+            serialization_config,
+            page_size,
+            create_first_page,
+        )
+    }
+
+    /// Opens a collection over any positional storage. `name` and `dir` locate its sidecar files.
+    pub fn from_storage(
+        name: &str,
+        dir: &str,
+        file: Box<dyn Storage>,
+        serialization_config: SerializationConfig,
+        page_size: Option<u64>,
+        create_first_page: bool,
+    ) -> Result<Self, CollectionFileError> {
+        let id_type = IdTypeTag::of::<T>();
+        let superblock = if file.len()? == 0 {
+            let superblock = CollectionSuperblock::new(
+                serialization_config,
+                page_size.unwrap_or(COLLECTION_PAGE_SIZE),
+                id_type,
+            );
+            file.write_all_at(&bincode::serialize(&superblock)?, 0)?;
+
+            superblock
+        } else {
+            let mut superblock = Self::read_superblock(file.as_ref())?;
+
+            if superblock.serialization_config() != serialization_config {
+                return Err(CollectionFileError::SerializationConfigMismatchError);
+            }
+
+            if page_size.is_some_and(|page_size| page_size != superblock.page_size()) {
+                return Err(CollectionFileError::PageSizeMismatchError);
+            }
+
+            // Ids may only change type along with a schema version bump, the documents are
+            // then expected to be migrated.
+            let stored_id_type = superblock.id_type();
+            if !stored_id_type.same_id_type(&id_type) {
+                if !stored_id_type.is_unknown()
+                    && stored_id_type.schema_version() >= id_type.schema_version()
+                {
+                    return Err(CollectionFileError::IdTypeMismatchError);
+                }
+
+                superblock.set_id_type(id_type);
+                file.write_all_at(&bincode::serialize(&superblock)?, 0)?;
+            }
+
+            superblock
+        };
+
         let mut page_number: u64 = 0;
         let mut encoded = vec![0u8; 1];
 
-        while let Ok(bytes_read) = file.read_at(&mut encoded, page_number * COLLECTION_PAGE_SIZE) {
+        while let Ok(bytes_read) = file.read_at(
+            &mut encoded,
+            Self::data_region_offset() + page_number * superblock.page_size(),
+        ) {
             if bytes_read < 1 {
                 break;
             }
@@ -53,13 +229,27 @@ impl<T: Document> CollectionFile<T> {
         }
 
         let mut collection = CollectionFile {
+            name: name.to_string(),
+            dir: dir.to_string(),
             number_of_pages: page_number,
             file,
+            collection_id: superblock.collection_id(),
+            serialization_config,
+            page_size: superblock.page_size(),
+            pages_read: AtomicU64::new(0),
+            headers_read: AtomicU64::new(0),
+            pages_written: AtomicU64::new(0),
+            cache_hits: AtomicU64::new(0),
+            page_cache: None,
+            write_back: None,
+            slot_directory: false,
+            index_sidecar_armed: AtomicBool::new(true),
+            on_commit: None,
             _marker: PhantomData,
         };
 
-        if page_number == 0 {
-            let first_page = CollectionPage::<T>::new(0);
+        if page_number == 0 && create_first_page {
+            let first_page = collection.new_page(0);
             collection.write_page(&first_page)?;
 
             collection.number_of_pages = 1;
@@ -68,19 +258,321 @@ impl<T: Document> CollectionFile<T> {
         Ok(collection)
     }
 
+    /// Reads and checks the superblock of an existing file, to learn how it is encoded
+    /// before opening it.
+    pub fn read_superblock(
+        file: &dyn Storage,
+    ) -> Result<CollectionSuperblock, CollectionFileError> {
+        // The superblock is written without padding, a file without pages is shorter.
+        let mut encoded = vec![0u8; SUPERBLOCK_SIZE.min(file.len()?) as usize];
+        file.read_exact_at(&mut encoded, 0)?;
+
+        let superblock = bincode::deserialize::<CollectionSuperblock>(&encoded[..])
+            .map_err(|_| CollectionFileError::InvalidSuperblockError)?;
+
+        if !superblock.is_valid() {
+            return Err(CollectionFileError::InvalidSuperblockError);
+        }
+
+        Ok(superblock)
+    }
+
+    /// Rewrites the superblock of an existing file with a new collection id, so pages of a
+    /// copied file can't be written to the original and the other way around. Page bytes
+    /// don't record the id, only the superblock is written.
+    pub fn renew_collection_id(file: &dyn Storage) -> Result<u64, CollectionFileError> {
+        let mut superblock = Self::read_superblock(file)?;
+        superblock.renew_collection_id();
+        file.write_all_at(&bincode::serialize(&superblock)?, 0)?;
+
+        Ok(superblock.collection_id())
+    }
+
+    /// Byte offset where the pages start, right after the superblock.
+    pub fn data_region_offset() -> u64 {
+        SUPERBLOCK_SIZE
+    }
+
+    /// Byte offset of a page in the file.
+    pub fn page_offset(&self, page_number: u64) -> u64 {
+        Self::data_region_offset() + page_number * self.page_size
+    }
+
+    pub fn new_page(&self, page_number: u64) -> CollectionPage<T> {
+        let mut page =
+            CollectionPage::with_page_size(page_number, self.serialization_config, self.page_size);
+        page.set_collection_id(self.collection_id);
+        if self.slot_directory {
+            page.enable_slot_directory();
+        }
+
+        page
+    }
+
+    /// Gives the pages created from now on a slot directory for faster lookups by id.
+    pub fn set_slot_directory(&mut self, slot_directory: bool) {
+        self.slot_directory = slot_directory;
+    }
+
+    pub fn slot_directory(&self) -> bool {
+        self.slot_directory
+    }
+
+    /// Free space a page needs to take a document of `document_size` bytes.
+    pub fn space_needed(&self, document_size: u64) -> Result<u64, CollectionFileError> {
+        let mut space_needed = document_size + self.serialization_config.document_separator_size();
+
+        if self.slot_directory {
+            space_needed += self
+                .serialization_config
+                .serialized_size(&(u64::MAX, u32::MAX))?
+                + self.serialization_config.document_separator_size();
+        }
+
+        Ok(space_needed)
+    }
+
+    /// Reads up to `count` pages starting at `start` with a single read, `count` is clamped
+    /// to the pages available.
+    pub fn read_pages(
+        &self,
+        start: u64,
+        count: u64,
+    ) -> Result<Vec<CollectionPage<T>>, CollectionFileError> {
+        let count = count.min(self.number_of_pages.saturating_sub(start));
+        if count == 0 {
+            return Ok(vec![]);
+        }
+
+        if let Some(write_back) = self.write_back.as_ref() {
+            return Ok(write_back.pages[start as usize..(start + count) as usize].to_vec());
+        }
+
+        let mut encoded = vec![0u8; (count * self.page_size) as usize];
+        self.file
+            .read_exact_at(&mut encoded, self.page_offset(start))?;
+        self.pages_read.fetch_add(count, Ordering::Relaxed);
+
+        encoded
+            .chunks(self.page_size as usize)
+            .map(|page_bytes| {
+                let mut collection_page = self
+                    .serialization_config
+                    .deserialize::<CollectionPage<T>>(page_bytes)?;
+                collection_page.set_serialization_config(self.serialization_config);
+                collection_page.set_collection_id(self.collection_id);
+                collection_page.set_page_size(self.page_size);
+
+                Ok(collection_page)
+            })
+            .collect()
+    }
+
+    /// Encoded bytes of a page, including the zero padding after its documents.
+    pub fn read_page_bytes(&self, page_number: u64) -> Result<Vec<u8>, CollectionFileError> {
+        if page_number >= self.number_of_pages {
+            return Err(CollectionFileError::PageNumberTooHighError);
+        }
+
+        if let Some(write_back) = self.write_back.as_ref() {
+            let mut encoded = self
+                .serialization_config
+                .serialize(&write_back.pages[page_number as usize])?;
+            encoded.resize(self.page_size as usize, 0);
+
+            return Ok(encoded);
+        }
+
+        if let Some(page_cache) = self.page_cache.as_ref() {
+            if let Some(encoded) = page_cache.pages.lock().unwrap().get(&page_number) {
+                self.cache_hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(encoded.clone());
+            }
+        }
+
+        let mut encoded = vec![0u8; self.page_size as usize];
+        self.file
+            .read_exact_at(&mut encoded, self.page_offset(page_number))?;
+        self.pages_read.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(page_cache) = self.page_cache.as_ref() {
+            let mut pages = page_cache.pages.lock().unwrap();
+            if pages.len() >= page_cache.capacity {
+                if let Some(evicted) = pages.keys().next().copied() {
+                    pages.remove(&evicted);
+                }
+            }
+            pages.insert(page_number, encoded.clone());
+        }
+
+        Ok(encoded)
+    }
+
+    /// Keeps up to `capacity` pages read in memory, a page write drops its copy. Only reads
+    /// of a whole page (`read_page`, `read_page_bytes`, ...) go through the cache.
+    pub fn set_page_cache(&mut self, capacity: usize) {
+        self.page_cache = Some(PageCache {
+            capacity,
+            pages: Mutex::new(HashMap::new()),
+        });
+    }
+
+    /// Moves every page in memory: reads are served from there and writes only change the
+    /// memory copy until `flush` writes all pages back at once, which also happens on drop.
+    /// Meant for small collections, other handles on the file only see flushed pages.
+    pub fn set_write_back(&mut self) -> Result<(), CollectionFileError> {
+        if self.write_back.is_some() {
+            return Ok(());
+        }
+
+        let pages = self.read_pages(0, self.number_of_pages)?;
+        self.write_back = Some(WriteBack {
+            pages,
+            dirty: AtomicBool::new(false),
+        });
+
+        Ok(())
+    }
+
+    /// Writes the pages held in write-back mode to the file with a single write. Does
+    /// nothing when no page changed or outside of write-back mode.
+    pub fn flush(&self) -> Result<(), CollectionFileError> {
+        let write_back = match self.write_back.as_ref() {
+            Some(write_back) if write_back.dirty.load(Ordering::Relaxed) => write_back,
+            _ => return Ok(()),
+        };
+
+        let mut encoded = Vec::with_capacity(write_back.pages.len() * self.page_size as usize);
+        let mut page_bytes = Vec::with_capacity(write_back.pages.len());
+        for page in write_back.pages.iter() {
+            let mut binary = self.serialization_config.serialize(page)?;
+            binary.resize(self.page_size as usize, 0);
+            encoded.extend_from_slice(&binary);
+            page_bytes.push(binary);
+        }
+
+        self.file
+            .write_all_at(&encoded, Self::data_region_offset())?;
+        self.pages_written
+            .fetch_add(write_back.pages.len() as u64, Ordering::Relaxed);
+        write_back.dirty.store(false, Ordering::Relaxed);
+
+        if let Some(on_commit) = self.on_commit.as_ref() {
+            self.file.sync_data()?;
+            for (page_number, binary) in page_bytes.iter().enumerate() {
+                (on_commit.0)(page_number as u64, binary);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_write_back(&self) -> bool {
+        self.write_back.is_some()
+    }
+
+    /// Whether pages written in write-back mode are still waiting for `flush`.
+    pub fn has_unflushed_pages(&self) -> bool {
+        self.write_back
+            .as_ref()
+            .is_some_and(|write_back| write_back.dirty.load(Ordering::Relaxed))
+    }
+
+    /// Reads pages into the page cache ahead of the queries needing them. Does nothing
+    /// without a page cache.
+    pub fn warm_cache(
+        &self,
+        pages: impl IntoIterator<Item = u64>,
+    ) -> Result<(), CollectionFileError> {
+        if self.page_cache.is_none() {
+            return Ok(());
+        }
+
+        for page_number in pages {
+            self.read_page_bytes(page_number)?;
+        }
+
+        Ok(())
+    }
+
+    /// Warms the cache with every page, up to its capacity.
+    pub fn warm_all(&self) -> Result<(), CollectionFileError> {
+        self.warm_cache(0..self.number_of_pages)
+    }
+
+    /// Header of a page and the still encoded bytes following it (slot directory, documents
+    /// and padding), for forwarding a page without decoding its documents. With a
+    /// self-describing codec the header has no fixed place and the bytes are the whole page.
+    pub fn read_page_raw(
+        &self,
+        page_number: u64,
+    ) -> Result<(CollectionPageHeader, Vec<u8>), CollectionFileError> {
+        let encoded = self.read_page_bytes(page_number)?;
+
+        if self.serialization_config.is_self_describing() {
+            let page = self
+                .serialization_config
+                .deserialize::<CollectionPage<IgnoredAny>>(&encoded[..])?;
+
+            return Ok((page.header, encoded));
+        }
+
+        let header = self
+            .serialization_config
+            .deserialize::<CollectionPageHeader>(&encoded[..])?;
+        let header_size = self.serialization_config.serialized_size(&header)? as usize;
+
+        Ok((header, encoded[header_size..].to_vec()))
+    }
+
     pub fn read_page(
         self: &Self,
         page_number: u64,
     ) -> Result<CollectionPage<T>, CollectionFileError> {
+        if let Some(write_back) = self.write_back.as_ref() {
+            return write_back
+                .pages
+                .get(page_number as usize)
+                .cloned()
+                .ok_or(CollectionFileError::PageNumberTooHighError);
+        }
+
+        self.read_page_as::<T>(page_number)
+    }
+
+    /// Documents of a page, with those that failed to decode when the policy skips them.
+    pub fn read_page_with_policy(
+        &self,
+        page_number: u64,
+        policy: ReadPolicy,
+    ) -> Result<(Vec<T>, Vec<DocError>), CollectionFileError> {
+        match policy {
+            ReadPolicy::Strict => Ok((self.read_page(page_number)?.into_documents(), vec![])),
+            ReadPolicy::SkipCorrupt => {
+                let encoded = self.read_page_bytes(page_number)?;
+                Ok(salvage_page::<T>(&self.serialization_config, &encoded)?)
+            }
+        }
+    }
+
+    /// Reads a page decoding its documents as `U`, used to read pages written with
+    /// another document format, for example before a migration.
+    pub fn read_page_as<U: Document>(
+        &self,
+        page_number: u64,
+    ) -> Result<CollectionPage<U>, CollectionFileError> {
         if page_number >= self.number_of_pages {
             return Err(CollectionFileError::PageNumberTooHighError);
         }
 
-        let offset = COLLECTION_PAGE_SIZE * page_number;
-        let mut encoded = vec![0u8; COLLECTION_PAGE_SIZE as usize];
-        self.file.read_at(&mut encoded, offset)?;
+        let encoded = self.read_page_bytes(page_number)?;
 
-        let collection_page = bincode::deserialize::<CollectionPage<T>>(&encoded[..])?;
+        let mut collection_page = self
+            .serialization_config
+            .deserialize::<CollectionPage<U>>(&encoded[..])?;
+        collection_page.set_serialization_config(self.serialization_config);
+        collection_page.set_page_size(self.page_size);
+        collection_page.set_collection_id(self.collection_id);
 
         Ok(collection_page)
     }
@@ -93,44 +585,259 @@ impl<T: Document> CollectionFile<T> {
             return Err(CollectionFileError::PageNumberTooHighError);
         }
 
-        let offset = COLLECTION_PAGE_SIZE * page_number;
+        if let Some(write_back) = self.write_back.as_ref() {
+            return Ok(write_back.pages[page_number as usize].header().clone());
+        }
+
+        let offset = self.page_offset(page_number);
+
+        if self.serialization_config.is_self_describing() {
+            // The header of a self-describing page has no fixed size, the whole page is read
+            // and its documents skipped.
+            let mut encoded = vec![0u8; self.page_size as usize];
+            self.file.read_exact_at(&mut encoded, offset)?;
+            self.headers_read.fetch_add(1, Ordering::Relaxed);
+
+            let page = self
+                .serialization_config
+                .deserialize::<CollectionPage<IgnoredAny>>(&encoded[..])?;
+
+            return Ok(page.header);
+        }
 
         let header_size: usize = std::mem::size_of::<CollectionPageHeader>();
 
         let mut encoded = vec![0u8; header_size];
-        self.file.read_at(&mut encoded, offset)?;
+        self.file.read_exact_at(&mut encoded, offset)?;
+        self.headers_read.fetch_add(1, Ordering::Relaxed);
 
-        let page_header = bincode::deserialize::<CollectionPageHeader>(&encoded[..])?;
+        let page_header = self
+            .serialization_config
+            .deserialize::<CollectionPageHeader>(&encoded[..])?;
 
         Ok(page_header)
     }
 
+    /// CRC-32 of the bytes of every page, to compare a collection with a replica without
+    /// transferring the pages.
+    pub fn page_checksums(&self) -> Result<Vec<u32>, CollectionFileError> {
+        (0..self.number_of_pages)
+            .map(|page_number| Ok(crc32(&self.read_page_bytes(page_number)?)))
+            .collect()
+    }
+
+    /// Ids of the documents of a page, read from their `id` field without decoding the rest
+    /// of the documents. Only works with a self-describing codec, which can skip fields.
+    pub fn read_page_ids<I: DeserializeOwned>(
+        &self,
+        page_number: u64,
+    ) -> Result<Vec<I>, CollectionFileError> {
+        let encoded = self.read_page_bytes(page_number)?;
+        let page = self
+            .serialization_config
+            .deserialize::<CollectionPage<DocumentId<I>>>(&encoded[..])?;
+
+        Ok(page
+            .into_documents()
+            .into_iter()
+            .map(|document| document.id)
+            .collect())
+    }
+
+    /// Pages holding at least one document, empty pages are skipped after reading their header.
+    pub fn non_empty_pages(
+        &self,
+    ) -> impl Iterator<Item = Result<CollectionPage<T>, CollectionFileError>> + '_ {
+        (0..self.number_of_pages).filter_map(move |page_number| {
+            match self.read_page_header(page_number) {
+                Ok(header) if header.number_of_documents() == 0 => None,
+                Ok(_) => Some(self.read_page(page_number)),
+                Err(e) => Some(Err(e)),
+            }
+        })
+    }
+
+    /// Writes a page over an existing one or appends it right after the last page.
+    /// Pages created for or read from another collection file are rejected.
     pub fn write_page(&mut self, page: &CollectionPage<T>) -> Result<(), CollectionFileError> {
-        if page.get_page_number() > self.number_of_pages + 1 {
+        if page.get_page_number() > self.number_of_pages {
             return Err(CollectionFileError::PageNumberTooHighError);
         }
 
+        if page
+            .collection_id()
+            .is_some_and(|collection_id| collection_id != self.collection_id)
+        {
+            return Err(CollectionFileError::ForeignPageError);
+        }
+
+        // A saved index doesn't describe the pages anymore once one of them changes.
+        if self.index_sidecar_armed.swap(false, Ordering::Relaxed) {
+            match fs::remove_file(self.index_sidecar_path()) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    self.index_sidecar_armed.store(true, Ordering::Relaxed);
+                    return Err(CollectionFileError::FileError(e));
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(write_back) = self.write_back.as_mut() {
+            let page_number = page.get_page_number();
+            let mut page = page.clone();
+            page.set_collection_id(self.collection_id);
+            if page_number == self.number_of_pages {
+                write_back.pages.push(page);
+                self.number_of_pages += 1;
+            } else {
+                write_back.pages[page_number as usize] = page;
+            }
+            write_back.dirty.store(true, Ordering::Relaxed);
+
+            return Ok(());
+        }
+
+        let offset = self.page_offset(page.get_page_number());
+
+        // Pages take their whole size in the file, so a read short of a page is an error.
+        let mut binary = self.serialization_config.serialize(page)?;
+        binary.resize(binary.len().max(self.page_size as usize), 0);
+
+        if let Some(page_cache) = self.page_cache.as_ref() {
+            page_cache
+                .pages
+                .lock()
+                .unwrap()
+                .remove(&page.get_page_number());
+        }
+        self.file.write_all_at(&binary, offset)?;
+        self.pages_written.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(on_commit) = self.on_commit.as_ref() {
+            self.file.sync_data()?;
+            (on_commit.0)(page.get_page_number(), &binary);
+        }
+
         if page.get_page_number() == self.number_of_pages {
             self.number_of_pages += 1;
         }
 
-        let offset = COLLECTION_PAGE_SIZE * page.get_page_number();
+        Ok(())
+    }
+
+    /// Syncs every page write and passes the written bytes to `on_commit`, for example to
+    /// ship them to a replica.
+    pub fn set_on_commit(&mut self, on_commit: CommitHook) {
+        self.on_commit = Some(on_commit);
+    }
 
-        let binary = bincode::serialize(page)?;
+    /// Random identifier of the file, stored in its superblock.
+    pub fn collection_id(&self) -> u64 {
+        self.collection_id
+    }
+
+    /// File the index is saved to by `save_index`, removed on the next page write.
+    pub fn index_sidecar_path(&self) -> String {
+        format!("{}/{}.index", self.dir, self.name)
+    }
+
+    /// Removes the index sidecar on the next page write, called after saving it.
+    pub fn arm_index_sidecar(&self) {
+        self.index_sidecar_armed.store(true, Ordering::Relaxed);
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn dir(&self) -> &str {
+        &self.dir
+    }
+
+    /// Checks that every page header records the page number of its position in the file,
+    /// failing on the first page that doesn't. A page written at the wrong offset would
+    /// otherwise be read as if it were the page at that position.
+    pub fn validate_page_numbers(&self) -> Result<(), CollectionFileError> {
+        for position in 0..self.number_of_pages {
+            let recorded = self.read_page_header(position)?.page_number();
+            if recorded != position {
+                return Err(CollectionFileError::PageNumberMismatchError { position, recorded });
+            }
+        }
 
-        self.file.write_all_at(&binary, offset)?;
         Ok(())
     }
 
+    /// Pages that were never written, such as a hole left by a write past the end of the
+    /// file: their header is zeroed, doesn't decode or records another page number.
+    pub fn find_page_gaps(&self) -> Result<Vec<u64>, CollectionFileError> {
+        let mut gaps = vec![];
+
+        for page_number in 0..self.number_of_pages {
+            match self.read_page_header(page_number) {
+                Ok(header) => {
+                    // Zeroed bytes decode to an empty page without free space, which no write
+                    // produces.
+                    let zeroed = header.number_of_documents() == 0 && header.space_available() == 0;
+                    if zeroed || header.page_number() != page_number {
+                        gaps.push(page_number);
+                    }
+                }
+                Err(CollectionFileError::SerializationError(_)) => gaps.push(page_number),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(gaps)
+    }
+
     pub fn number_of_pages(&self) -> u64 {
         self.number_of_pages
     }
+
+    /// Makes every page written so far durable, flushing them first in write-back mode.
+    pub fn sync_data(&self) -> Result<(), CollectionFileError> {
+        self.flush()?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    pub fn serialization_config(&self) -> SerializationConfig {
+        self.serialization_config
+    }
+
+    pub fn page_size(&self) -> u64 {
+        self.page_size
+    }
+
+    /// Bytes of a page available to documents.
+    pub fn page_data_size(&self) -> u64 {
+        self.new_page(0).data_size()
+    }
+
+    pub fn io_stats(&self) -> IoStats {
+        IoStats {
+            pages_read: self.pages_read.load(Ordering::Relaxed),
+            headers_read: self.headers_read.load(Ordering::Relaxed),
+            pages_written: self.pages_written.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl<T: Document> Drop for CollectionFile<T> {
+    // Errors can't be reported from here, call `flush` to see them.
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::collection_page::COLLECTION_PAGE_DATA_SIZE;
     use crate::document::HasId;
+    use crate::serialization::IntEncoding;
     use serde_derive::{Deserialize, Serialize};
     use tempfile::tempdir;
 
@@ -228,4 +935,607 @@ mod tests {
 
         assert_eq!(collection_page_0, collection_page_from_file_0_updated);
     }
+
+    #[test]
+    fn test_write_and_read_with_varint_encoding() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let config = SerializationConfig::default().with_int_encoding(IntEncoding::Varint);
+        let mut collection =
+            CollectionFile::<MyDocument>::open("collection", dir_name, config).unwrap();
+
+        let mut collection_page = collection.new_page(0);
+        collection_page
+            .insert_document(&MyDocument { id: 1 })
+            .unwrap();
+        collection.write_page(&collection_page).unwrap();
+
+        let reopened = CollectionFile::<MyDocument>::open("collection", dir_name, config).unwrap();
+        let collection_page_from_file = reopened.read_page(0).unwrap();
+
+        assert_eq!(collection_page, collection_page_from_file);
+        assert_eq!(
+            collection_page_from_file.header.space_available(),
+            COLLECTION_PAGE_DATA_SIZE - 1
+        );
+    }
+
+    #[test]
+    fn test_reopen_with_different_serialization_config() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+
+        CollectionFile::<MyDocument>::new("collection", dir_name).unwrap();
+
+        let config = SerializationConfig::default().with_int_encoding(IntEncoding::Varint);
+        let reopened = CollectionFile::<MyDocument>::open("collection", dir_name, config);
+
+        assert!(matches!(
+            reopened,
+            Err(CollectionFileError::SerializationConfigMismatchError)
+        ));
+    }
+
+    #[test]
+    fn test_reopen_with_different_id_type() {
+        #[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+        struct SmallIdDocument {
+            id: u32,
+        }
+
+        impl HasId for SmallIdDocument {
+            type Id = u32;
+
+            fn id(&self) -> u32 {
+                self.id
+            }
+        }
+
+        #[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+        struct MigratedDocument {
+            id: u64,
+        }
+
+        impl HasId for MigratedDocument {
+            type Id = u64;
+
+            fn id(&self) -> u64 {
+                self.id
+            }
+
+            fn schema_version() -> u32 {
+                1
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+
+        CollectionFile::<SmallIdDocument>::new("collection", dir_name).unwrap();
+
+        let reopened = CollectionFile::<MyDocument>::new("collection", dir_name);
+        assert!(matches!(
+            reopened,
+            Err(CollectionFileError::IdTypeMismatchError)
+        ));
+
+        // A schema version bump lets the ids change type, the new type is recorded.
+        CollectionFile::<MigratedDocument>::new("collection", dir_name).unwrap();
+        assert!(matches!(
+            CollectionFile::<SmallIdDocument>::new("collection", dir_name),
+            Err(CollectionFileError::IdTypeMismatchError)
+        ));
+    }
+
+    #[test]
+    fn test_write_far_future_page_is_rejected() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = CollectionFile::<MyDocument>::new("collection", dir_name).unwrap();
+
+        let result = collection.write_page(&CollectionPage::new(1_000));
+
+        assert!(matches!(
+            result,
+            Err(CollectionFileError::PageNumberTooHighError)
+        ));
+        assert_eq!(collection.number_of_pages(), 1);
+
+        let reopened = CollectionFile::<MyDocument>::new("collection", dir_name).unwrap();
+        assert_eq!(reopened.number_of_pages(), 1);
+    }
+
+    #[test]
+    fn test_validate_page_numbers_finds_a_misplaced_page() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = CollectionFile::<MyDocument>::new("collection", dir_name).unwrap();
+
+        for page_number in 1..4 {
+            collection
+                .write_page(&collection.new_page(page_number))
+                .unwrap();
+        }
+        collection.validate_page_numbers().unwrap();
+
+        // Page 3 copied over page 1, as a write landing at the wrong offset would.
+        let misplaced = collection.read_page_bytes(3).unwrap();
+        collection
+            .file
+            .write_all_at(&misplaced, collection.page_offset(1))
+            .unwrap();
+
+        assert!(matches!(
+            collection.validate_page_numbers(),
+            Err(CollectionFileError::PageNumberMismatchError {
+                position: 1,
+                recorded: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn test_write_page_from_another_collection_is_rejected() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection_a = CollectionFile::<MyDocument>::new("collection_a", dir_name).unwrap();
+        let mut collection_b = CollectionFile::<MyDocument>::new("collection_b", dir_name).unwrap();
+
+        let page_from_a = collection_a.read_page(0).unwrap();
+
+        assert!(matches!(
+            collection_b.write_page(&page_from_a),
+            Err(CollectionFileError::ForeignPageError)
+        ));
+        collection_a.write_page(&page_from_a).unwrap();
+    }
+
+    #[test]
+    fn test_write_page_skipping_a_page_is_rejected() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let path = format!("{}/collection.collection", dir_name);
+        let mut collection = CollectionFile::<MyDocument>::new("collection", dir_name).unwrap();
+
+        collection.write_page(&CollectionPage::new(0)).unwrap();
+        let file_size = std::fs::metadata(&path).unwrap().len();
+
+        let result = collection.write_page(&CollectionPage::new(2));
+
+        assert!(matches!(
+            result,
+            Err(CollectionFileError::PageNumberTooHighError)
+        ));
+        assert_eq!(collection.number_of_pages(), 1);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), file_size);
+
+        collection.write_page(&CollectionPage::new(1)).unwrap();
+        assert_eq!(collection.number_of_pages(), 2);
+    }
+
+    #[test]
+    fn test_find_page_gaps_reports_unwritten_pages() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let collection = CollectionFile::<MyDocument>::new("collection", dir_name).unwrap();
+        assert!(collection.find_page_gaps().unwrap().is_empty());
+
+        // Page 2 written straight to the file, past the end, leaving page 1 zeroed.
+        let mut encoded = bincode::serialize(&collection.new_page(2)).unwrap();
+        encoded.resize(collection.page_size as usize, 0);
+        collection
+            .file
+            .write_all_at(&encoded, collection.page_offset(2))
+            .unwrap();
+        drop(collection);
+
+        let reopened = CollectionFile::<MyDocument>::new("collection", dir_name).unwrap();
+        assert_eq!(reopened.number_of_pages(), 3);
+        assert_eq!(reopened.find_page_gaps().unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn test_page_offsets_match_written_positions() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let path = format!("{}/collection.collection", dir_name);
+        let mut collection = CollectionFile::<MyDocument>::new("collection", dir_name).unwrap();
+
+        let mut page = collection.new_page(1);
+        page.insert_document(&MyDocument { id: 1 }).unwrap();
+        collection.write_page(&page).unwrap();
+
+        let file = std::fs::read(&path).unwrap();
+        let encoded = bincode::serialize(&page).unwrap();
+
+        assert_eq!(
+            CollectionFile::<MyDocument>::data_region_offset(),
+            SUPERBLOCK_SIZE
+        );
+        assert_eq!(collection.page_offset(0), SUPERBLOCK_SIZE);
+        assert_eq!(
+            collection.page_offset(1) - collection.page_offset(0),
+            COLLECTION_PAGE_SIZE
+        );
+
+        let offset = collection.page_offset(1) as usize;
+        assert_eq!(&file[offset..offset + encoded.len()], &encoded[..]);
+        assert_eq!(file.len(), offset + COLLECTION_PAGE_SIZE as usize);
+    }
+
+    #[test]
+    fn test_non_empty_pages_skips_empty_pages() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = CollectionFile::<MyDocument>::new("collection", dir_name).unwrap();
+
+        for page_number in 0..5 {
+            let mut page = collection.new_page(page_number);
+            if page_number % 2 == 0 {
+                page.insert_document(&MyDocument { id: page_number })
+                    .unwrap();
+            }
+            collection.write_page(&page).unwrap();
+        }
+
+        let before = collection.io_stats();
+        let page_numbers: Vec<u64> = collection
+            .non_empty_pages()
+            .map(|page| page.unwrap().get_page_number())
+            .collect();
+        let after = collection.io_stats();
+
+        assert_eq!(page_numbers, vec![0, 2, 4]);
+        assert_eq!(after.pages_read - before.pages_read, 3);
+        assert_eq!(after.headers_read - before.headers_read, 5);
+    }
+
+    #[test]
+    fn test_reopen_with_different_page_size() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let config = SerializationConfig::default();
+        let mut collection = CollectionFile::<MyDocument>::open_with_page_size(
+            "collection",
+            dir_name,
+            config,
+            Some(8_192),
+            true,
+        )
+        .unwrap();
+
+        let mut page = collection.new_page(1);
+        page.insert_document(&MyDocument { id: 1 }).unwrap();
+        collection.write_page(&page).unwrap();
+
+        assert_eq!(collection.page_offset(1), SUPERBLOCK_SIZE + 8_192);
+        assert_eq!(
+            collection.page_data_size(),
+            8_192 - crate::collection_page::COLLECTION_PAGE_HEADER_SIZE
+        );
+
+        let mismatch = CollectionFile::<MyDocument>::open_with_page_size(
+            "collection",
+            dir_name,
+            config,
+            Some(62_000),
+            true,
+        );
+        assert!(matches!(
+            mismatch,
+            Err(CollectionFileError::PageSizeMismatchError)
+        ));
+
+        let reopened = CollectionFile::<MyDocument>::open("collection", dir_name, config).unwrap();
+        assert_eq!(reopened.page_size(), 8_192);
+        assert_eq!(reopened.number_of_pages(), 2);
+        assert_eq!(
+            reopened.read_page(1).unwrap().find_document(1),
+            Some(MyDocument { id: 1 })
+        );
+    }
+
+    #[test]
+    fn test_read_pages_matches_individual_reads() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = CollectionFile::<MyDocument>::new("collection", dir_name).unwrap();
+
+        for page_number in 0..12 {
+            let mut page = collection.new_page(page_number);
+            for id in 0..page_number {
+                page.insert_document(&MyDocument {
+                    id: page_number * 100 + id,
+                })
+                .unwrap();
+            }
+            collection.write_page(&page).unwrap();
+        }
+
+        let pages = collection.read_pages(1, 10).unwrap();
+
+        assert_eq!(pages.len(), 10);
+        for (page_number, page) in (1..11).zip(pages.iter()) {
+            assert_eq!(*page, collection.read_page(page_number).unwrap());
+        }
+
+        assert_eq!(collection.read_pages(8, 10).unwrap().len(), 4);
+        assert!(collection.read_pages(12, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_page_checksums_spot_the_changed_page() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = CollectionFile::<MyDocument>::new("collection", dir_name).unwrap();
+        let mut replica = CollectionFile::<MyDocument>::new("replica", dir_name).unwrap();
+
+        for file in [&mut collection, &mut replica] {
+            for page_number in 0..4 {
+                let mut page = file.new_page(page_number);
+                page.insert_document(&MyDocument { id: page_number })
+                    .unwrap();
+                file.write_page(&page).unwrap();
+            }
+        }
+
+        let checksums = collection.page_checksums().unwrap();
+        assert_eq!(checksums.len(), 4);
+        assert_eq!(replica.page_checksums().unwrap(), checksums);
+
+        let mut page = replica.read_page(2).unwrap();
+        page.insert_document(&MyDocument { id: 10 }).unwrap();
+        replica.write_page(&page).unwrap();
+
+        let replica_checksums = replica.page_checksums().unwrap();
+        let changed: Vec<usize> = (0..4)
+            .filter(|&i| checksums[i] != replica_checksums[i])
+            .collect();
+        assert_eq!(changed, vec![2]);
+    }
+
+    #[test]
+    fn test_failed_page_write_is_reported_and_leaves_file_usable() {
+        use crate::storage::FaultyStorage;
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .read(true)
+            .open(format!("{}/collection.collection", dir_name))
+            .unwrap();
+        // The superblock and the empty page 0 are the first two writes.
+        let mut collection = CollectionFile::<MyDocument>::from_storage(
+            "collection",
+            dir_name,
+            Box::new(FaultyStorage::failing_nth_write(file, 4)),
+            SerializationConfig::default(),
+            None,
+            true,
+        )
+        .unwrap();
+
+        let mut page = collection.new_page(1);
+        page.insert_document(&MyDocument { id: 1 }).unwrap();
+        collection.write_page(&page).unwrap();
+
+        let mut page = collection.new_page(2);
+        page.insert_document(&MyDocument { id: 2 }).unwrap();
+
+        assert!(matches!(
+            collection.write_page(&page),
+            Err(CollectionFileError::FileError(_))
+        ));
+        assert_eq!(collection.number_of_pages(), 2);
+        assert_eq!(collection.io_stats().pages_written, 2);
+        assert!(matches!(
+            collection.read_page(2),
+            Err(CollectionFileError::PageNumberTooHighError)
+        ));
+
+        collection.write_page(&page).unwrap();
+        assert_eq!(collection.number_of_pages(), 3);
+        assert_eq!(
+            collection.read_page(1).unwrap().find_document(1),
+            Some(MyDocument { id: 1 })
+        );
+        assert_eq!(
+            collection.read_page(2).unwrap().find_document(2),
+            Some(MyDocument { id: 2 })
+        );
+    }
+
+    #[test]
+    fn test_short_reads_are_completed_and_a_truncated_page_is_an_error() {
+        use crate::storage::FaultyStorage;
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let path = format!("{}/collection.collection", dir_name);
+        let mut collection = CollectionFile::<MyDocument>::new("collection", dir_name).unwrap();
+        for id in 1..3 {
+            let mut page = collection.new_page(id);
+            page.insert_document(&MyDocument { id }).unwrap();
+            collection.write_page(&page).unwrap();
+        }
+        drop(collection);
+
+        let open_with_short_reads = || {
+            let file = OpenOptions::new()
+                .write(true)
+                .read(true)
+                .open(&path)
+                .unwrap();
+            CollectionFile::<MyDocument>::from_storage(
+                "collection",
+                dir_name,
+                Box::new(FaultyStorage::failing_after(file, usize::MAX).with_short_reads()),
+                SerializationConfig::default(),
+                None,
+                true,
+            )
+            .unwrap()
+        };
+
+        let collection = open_with_short_reads();
+        assert_eq!(
+            collection.read_page(2).unwrap().find_document(2),
+            Some(MyDocument { id: 2 })
+        );
+        assert_eq!(collection.read_pages(0, 3).unwrap().len(), 3);
+        drop(collection);
+
+        // Half of the last page is gone, reading it must fail rather than decode zeros.
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(SUPERBLOCK_SIZE + 5 * COLLECTION_PAGE_SIZE / 2)
+            .unwrap();
+        let collection = open_with_short_reads();
+        assert_eq!(collection.number_of_pages(), 3);
+        assert!(collection.read_page(1).is_ok());
+        assert!(matches!(
+            collection.read_page(2),
+            Err(CollectionFileError::FileError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn test_panic_during_page_write_leaves_other_pages_readable() {
+        use crate::storage::FaultyStorage;
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .read(true)
+            .open(format!("{}/collection.collection", dir_name))
+            .unwrap();
+        // The superblock, the empty page 0 and page 1 are the first three writes.
+        let mut collection = CollectionFile::<MyDocument>::from_storage(
+            "collection",
+            dir_name,
+            Box::new(FaultyStorage::panicking_at_write(file, 4)),
+            SerializationConfig::default(),
+            None,
+            true,
+        )
+        .unwrap();
+
+        let mut page = collection.new_page(1);
+        page.insert_document(&MyDocument { id: 1 }).unwrap();
+        collection.write_page(&page).unwrap();
+
+        let mut page = collection.new_page(2);
+        page.insert_document(&MyDocument { id: 2 }).unwrap();
+        let torn = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            collection.write_page(&page)
+        }));
+        assert!(torn.is_err());
+        drop(collection);
+
+        let reopened = CollectionFile::<MyDocument>::new("collection", dir_name).unwrap();
+        assert_eq!(
+            reopened.read_page(1).unwrap().find_document(1),
+            Some(MyDocument { id: 1 })
+        );
+        assert!(matches!(
+            reopened.read_page(2),
+            Err(CollectionFileError::FileError(_))
+        ));
+    }
+
+    #[test]
+    fn test_read_page_raw_splits_header_from_documents() {
+        use crate::collection_page::Slot;
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = CollectionFile::<MyDocument>::new("collection", dir_name).unwrap();
+
+        let mut page = collection.new_page(1);
+        for id in 0..5 {
+            page.insert_document(&MyDocument { id }).unwrap();
+        }
+        collection.write_page(&page).unwrap();
+
+        let (header, raw) = collection.read_page_raw(1).unwrap();
+        let full_page = collection.read_page(1).unwrap();
+
+        assert_eq!(header, full_page.header);
+        let (_, documents) = collection
+            .serialization_config()
+            .deserialize::<(Option<Vec<Slot>>, Vec<MyDocument>)>(&raw)
+            .unwrap();
+        assert_eq!(&documents, full_page.documents());
+    }
+
+    #[test]
+    fn test_warm_cache_turns_reads_into_cache_hits() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = CollectionFile::<MyDocument>::new("collection", dir_name).unwrap();
+
+        for page_number in 1..4 {
+            let mut page = collection.new_page(page_number);
+            page.insert_document(&MyDocument { id: page_number })
+                .unwrap();
+            collection.write_page(&page).unwrap();
+        }
+        collection.set_page_cache(8);
+
+        collection.warm_cache([1, 2]).unwrap();
+        let before = collection.io_stats();
+
+        assert_eq!(
+            collection.read_page(1).unwrap().find_document(1),
+            Some(MyDocument { id: 1 })
+        );
+        collection.read_page(2).unwrap();
+        collection.read_page(3).unwrap();
+
+        let after = collection.io_stats();
+        assert_eq!(after.cache_hits - before.cache_hits, 2);
+        assert_eq!(after.pages_read - before.pages_read, 1);
+
+        let mut page = collection.read_page(1).unwrap();
+        page.insert_document(&MyDocument { id: 10 }).unwrap();
+        collection.write_page(&page).unwrap();
+
+        let before = collection.io_stats();
+        assert_eq!(
+            collection.read_page(1).unwrap().find_document(10),
+            Some(MyDocument { id: 10 })
+        );
+        assert_eq!(collection.io_stats().pages_read - before.pages_read, 1);
+
+        collection.warm_all().unwrap();
+        let before = collection.io_stats();
+        for page_number in 0..4 {
+            collection.read_page(page_number).unwrap();
+        }
+        assert_eq!(collection.io_stats().cache_hits - before.cache_hits, 4);
+        assert_eq!(collection.io_stats().pages_read, before.pages_read);
+    }
 }
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::collection_file::{CollectionFile, CollectionFileError};
+use crate::collection_page::CollectionPageError;
+use crate::document::{Document, HasId};
+use crate::serialization::SerializationConfig;
+
+/// A past version of a document, numbered from 1 in the order it was replaced.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryEntry<T> {
+    version: u64,
+    document: T,
+}
+
+impl<T: HasId> HasId for HistoryEntry<T> {
+    type Id = (<T as HasId>::Id, u64);
+
+    fn id(&self) -> Self::Id {
+        (self.document.id(), self.version)
+    }
+}
+
+#[derive(Debug)]
+pub enum HistoryError {
+    FileError(CollectionFileError),
+    PageError(CollectionPageError),
+}
+
+impl From<CollectionFileError> for HistoryError {
+    fn from(err: CollectionFileError) -> Self {
+        HistoryError::FileError(err)
+    }
+}
+
+impl From<CollectionPageError> for HistoryError {
+    fn from(err: CollectionPageError) -> Self {
+        HistoryError::PageError(err)
+    }
+}
+
+/// Past versions of the documents of a collection, kept in `{name}.history.collection`.
+pub struct DocumentHistory<T: Document> {
+    history_file: CollectionFile<HistoryEntry<T>>,
+    versions: HashMap<<T as HasId>::Id, Vec<(u64, u64)>>,
+}
+
+impl<T: Document> DocumentHistory<T> {
+    pub fn open(
+        name: &str,
+        dir: &str,
+        serialization_config: SerializationConfig,
+    ) -> Result<Self, HistoryError> {
+        let history_file = CollectionFile::<HistoryEntry<T>>::open(
+            &format!("{}.history", name),
+            dir,
+            serialization_config,
+        )?;
+        let mut versions: HashMap<<T as HasId>::Id, Vec<(u64, u64)>> = HashMap::new();
+
+        for page_number in 0..history_file.number_of_pages() {
+            let page = history_file.read_page(page_number)?;
+
+            for entry in page.documents().iter() {
+                versions
+                    .entry(entry.document.id())
+                    .or_default()
+                    .push((entry.version, page_number));
+            }
+        }
+
+        for entries in versions.values_mut() {
+            entries.sort();
+        }
+
+        Ok(DocumentHistory {
+            history_file,
+            versions,
+        })
+    }
+
+    /// Stores `previous` as the newest past version of its document.
+    pub fn record(&mut self, previous: &T) -> Result<(), HistoryError> {
+        let entries = self.versions.entry(previous.id()).or_default();
+        let version = entries.last().map_or(1, |(version, _)| version + 1);
+        let entry = HistoryEntry {
+            version,
+            document: previous.clone(),
+        };
+
+        let last_page_number = self.history_file.number_of_pages() - 1;
+        let mut page = self.history_file.read_page(last_page_number)?;
+
+        if let Err(CollectionPageError::NoFreeSpaceAvailable) = page.insert_document(&entry) {
+            page = self.history_file.new_page(last_page_number + 1);
+            page.insert_document(&entry)?;
+        }
+
+        self.history_file.write_page(&page)?;
+        entries.push((version, page.get_page_number()));
+
+        Ok(())
+    }
+
+    /// Past versions of a document, oldest first.
+    pub fn versions(&self, id: <T as HasId>::Id) -> Result<Vec<T>, HistoryError> {
+        let mut documents = vec![];
+
+        for (version, page_number) in self.versions.get(&id).into_iter().flatten() {
+            let page = self.history_file.read_page(*page_number)?;
+
+            if let Some(entry) = page.find_document((id, *version)) {
+                documents.push(entry.document);
+            }
+        }
+
+        Ok(documents)
+    }
+
+    /// Removes all but the `keep` most recent past versions of a document,
+    /// returning how many versions were removed.
+    pub fn prune(&mut self, id: <T as HasId>::Id, keep: usize) -> Result<usize, HistoryError> {
+        let entries = match self.versions.get_mut(&id) {
+            Some(entries) if entries.len() > keep => entries,
+            _ => return Ok(0),
+        };
+
+        let pruned: Vec<(u64, u64)> = entries.drain(..entries.len() - keep).collect();
+        let mut pruned_by_page: HashMap<u64, Vec<u64>> = HashMap::new();
+        for (version, page_number) in pruned.iter() {
+            pruned_by_page
+                .entry(*page_number)
+                .or_default()
+                .push(*version);
+        }
+
+        for (page_number, versions) in pruned_by_page {
+            let mut page = self.history_file.read_page(page_number)?;
+
+            for version in versions {
+                page.remove_document((id, version))?;
+            }
+
+            self.history_file.write_page(&page)?;
+        }
+
+        Ok(pruned.len())
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::fs::File;
+
+use crate::collection::CollectionError;
+use crate::collection_file::{CollectionFile, CollectionFileError};
+use crate::collection_page::CollectionPage;
+use crate::document::{Document, Filter, HasId};
+
+/// Read-only copy of a collection file held in memory, for analytics that shouldn't touch
+/// the live file. Built with a single read of every page, the file isn't used afterwards
+/// and later writes to it aren't seen.
+pub struct InMemoryCollection<T: Document> {
+    pages: Vec<CollectionPage<T>>,
+    id_to_page: HashMap<<T as HasId>::Id, usize>,
+}
+
+impl<T: Document> InMemoryCollection<T> {
+    /// Loads `{dir}/{name}.collection`, which must exist. The file is opened read-only and
+    /// decoded with the serialization config recorded in it.
+    pub fn load_into_memory(name: &str, dir: &str) -> Result<Self, CollectionError> {
+        let file = File::open(format!("{}/{}.collection", dir, name))
+            .map_err(CollectionFileError::FileError)?;
+        let serialization_config =
+            CollectionFile::<T>::read_superblock(&file)?.serialization_config();
+        let collection_file = CollectionFile::<T>::from_storage(
+            name,
+            dir,
+            Box::new(file),
+            serialization_config,
+            None,
+            false,
+        )?;
+
+        let pages = collection_file.read_pages(0, collection_file.number_of_pages())?;
+        let mut id_to_page = HashMap::new();
+        for (position, page) in pages.iter().enumerate() {
+            for document in page.documents().iter() {
+                id_to_page.insert(document.id(), position);
+            }
+        }
+
+        Ok(InMemoryCollection { pages, id_to_page })
+    }
+
+    pub fn find_by_id(&self, id: <T as HasId>::Id) -> Option<T> {
+        let position = *self.id_to_page.get(&id)?;
+        self.pages[position].find_document(id)
+    }
+
+    pub fn find_by(&self, filter: Filter<T>) -> Vec<T> {
+        self.pages
+            .iter()
+            .flat_map(|page| page.documents().iter())
+            .filter(|document| filter(document))
+            .cloned()
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.id_to_page.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collection::Collection;
+    use serde_derive::{Deserialize, Serialize};
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+    struct MyDocument {
+        id: u64,
+        name: String,
+    }
+
+    impl HasId for MyDocument {
+        type Id = u64;
+
+        fn id(&self) -> u64 {
+            self.id
+        }
+    }
+
+    #[test]
+    fn test_reads_survive_the_file_being_deleted() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+        // Three documents per page.
+        for id in 0..7 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: "a".repeat(20_000),
+                })
+                .unwrap();
+        }
+        drop(collection);
+
+        let in_memory =
+            InMemoryCollection::<MyDocument>::load_into_memory("test", dir_name).unwrap();
+        fs::remove_file(format!("{}/test.collection", dir_name)).unwrap();
+
+        assert_eq!(in_memory.len(), 7);
+        assert_eq!(in_memory.find_by_id(5).unwrap().name, "a".repeat(20_000));
+        assert!(in_memory.find_by_id(7).is_none());
+        assert_eq!(in_memory.find_by(|document| document.id % 2 == 0).len(), 4);
+
+        assert!(InMemoryCollection::<MyDocument>::load_into_memory("test", dir_name).is_err());
+    }
+}
@@ -1,4 +1,11 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs;
+use std::hash::BuildHasher;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
 use crate::collection_file::CollectionFileError;
 use crate::{
@@ -6,15 +13,376 @@ use crate::{
     document::{Document, HasId},
 };
 
-pub type IdToPageMap<T> = HashMap<<T as HasId>::Id, u64>;
+#[cfg(not(feature = "fxhash"))]
+pub type IndexHasher = std::collections::hash_map::RandomState;
+#[cfg(feature = "fxhash")]
+pub type IndexHasher = rustc_hash::FxBuildHasher;
+
+pub type IdToPageMap<T, S = std::collections::hash_map::RandomState> =
+    HashMap<<T as HasId>::Id, u64, S>;
+
+/// Contents of the index sidecar, only valid for the file and page count it was saved with.
+#[derive(Serialize, Deserialize)]
+struct IndexSidecar<I> {
+    collection_id: u64,
+    number_of_pages: u64,
+    entries: Vec<(I, u64)>,
+}
+
+/// Saves the index next to the collection file, so the next open doesn't rescan the pages.
+pub fn save_index<T: Document, S: BuildHasher>(
+    collection_file: &CollectionFile<T>,
+    index: &IdToPageMap<T, S>,
+) -> Result<(), CollectionFileError>
+where
+    <T as HasId>::Id: Serialize,
+{
+    let sidecar = IndexSidecar {
+        collection_id: collection_file.collection_id(),
+        number_of_pages: collection_file.number_of_pages(),
+        entries: index.iter().map(|(id, page)| (*id, *page)).collect(),
+    };
+    let encoded = collection_file.serialization_config().serialize(&sidecar)?;
+
+    let path = collection_file.index_sidecar_path();
+    let tmp_path = format!("{}.tmp", path);
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(&encoded)?;
+    file.sync_data()?;
+    fs::rename(&tmp_path, &path)?;
+
+    collection_file.arm_index_sidecar();
+
+    Ok(())
+}
+
+/// Loads the index saved by `save_index`, `None` when there is none or it doesn't match
+/// the collection file.
+pub fn load_index<T: Document, S: BuildHasher + Default>(
+    collection_file: &CollectionFile<T>,
+) -> Result<Option<IdToPageMap<T, S>>, CollectionFileError>
+where
+    <T as HasId>::Id: DeserializeOwned,
+{
+    let encoded = match fs::read(collection_file.index_sidecar_path()) {
+        Ok(encoded) => encoded,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(CollectionFileError::FileError(e)),
+    };
+
+    let sidecar = match collection_file
+        .serialization_config()
+        .deserialize::<IndexSidecar<<T as HasId>::Id>>(&encoded)
+    {
+        Ok(sidecar) => sidecar,
+        Err(_) => return Ok(None),
+    };
+
+    if sidecar.collection_id != collection_file.collection_id()
+        || sidecar.number_of_pages != collection_file.number_of_pages()
+    {
+        return Ok(None);
+    }
+
+    Ok(Some(sidecar.entries.into_iter().collect()))
+}
+
+/// Writes an index as a little endian `u64` entry count followed by, for every entry,
+/// the `u32` length of the bincode encoded id, the encoded id and the `u64` page number.
+pub fn write_index<T: Document, S, W: Write>(
+    index: &IdToPageMap<T, S>,
+    mut w: W,
+) -> Result<(), CollectionFileError>
+where
+    <T as HasId>::Id: Serialize,
+{
+    w.write_all(&(index.len() as u64).to_le_bytes())?;
+
+    for (id, page_number) in index.iter() {
+        write_entry(&mut w, id, *page_number)?;
+    }
+
+    Ok(())
+}
+
+/// Writes one entry of the `write_index` format, returning the number of bytes written.
+fn write_entry<I: Serialize, W: Write>(
+    w: &mut W,
+    id: &I,
+    page_number: u64,
+) -> Result<u64, CollectionFileError> {
+    let encoded_id = bincode::serialize(id)?;
+    w.write_all(&(encoded_id.len() as u32).to_le_bytes())?;
+    w.write_all(&encoded_id)?;
+    w.write_all(&page_number.to_le_bytes())?;
+
+    Ok(4 + encoded_id.len() as u64 + 8)
+}
+
+/// Reads one entry written by `write_entry`, `None` at the end of the input.
+fn read_entry<I: DeserializeOwned, R: Read>(
+    r: &mut R,
+) -> Result<Option<(I, u64)>, CollectionFileError> {
+    let mut u32_bytes = [0u8; 4];
+    let mut u64_bytes = [0u8; 8];
+
+    match r.read_exact(&mut u32_bytes) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(CollectionFileError::FileError(e)),
+    }
+    let mut encoded_id = vec![0u8; u32::from_le_bytes(u32_bytes) as usize];
+    r.read_exact(&mut encoded_id)?;
+    r.read_exact(&mut u64_bytes)?;
+
+    Ok(Some((
+        bincode::deserialize(&encoded_id)?,
+        u64::from_le_bytes(u64_bytes),
+    )))
+}
+
+/// Reads an index written by `write_index`.
+pub fn read_index<T: Document, S: BuildHasher + Default, R: Read>(
+    mut r: R,
+) -> Result<IdToPageMap<T, S>, CollectionFileError>
+where
+    <T as HasId>::Id: DeserializeOwned,
+{
+    let mut u64_bytes = [0u8; 8];
+
+    r.read_exact(&mut u64_bytes)?;
+    let number_of_entries = u64::from_le_bytes(u64_bytes);
+    let mut index = HashMap::<<T>::Id, u64, S>::default();
+
+    for _ in 0..number_of_entries {
+        let (id, page_number) = read_entry(&mut r)?.ok_or_else(|| {
+            CollectionFileError::FileError(std::io::ErrorKind::UnexpectedEof.into())
+        })?;
+        index.insert(id, page_number);
+    }
+
+    Ok(index)
+}
+
+/// Entries between two fences of a `SortedIndex`, the most a lookup reads from the file
+/// unless the memory budget asks for fewer fences.
+const SORTED_INDEX_FENCE_INTERVAL: u64 = 64;
+
+/// Most run files merged at once while building a `SortedIndex`.
+const SORTED_INDEX_MERGE_FAN_IN: usize = 64;
+
+/// Id to page index kept sorted by id in `{name}.sorted_index`, in the entry format of
+/// `write_index` without the entry count. Only every `fence_interval`th id is kept in
+/// memory, with the offset of its entry, a lookup reads the entries after it.
+pub struct SortedIndex<I> {
+    path: String,
+    fences: Vec<(I, u64)>,
+    fence_interval: u64,
+    len: u64,
+    runs: usize,
+}
+
+impl<I: Ord + DeserializeOwned> SortedIndex<I> {
+    /// Page holding the document with the given id, as of when the index was built.
+    pub fn get(&self, id: &I) -> Result<Option<u64>, CollectionFileError> {
+        let fence = self.fences.partition_point(|(fence_id, _)| fence_id <= id);
+        if fence == 0 {
+            return Ok(None);
+        }
+
+        let mut file = BufReader::new(fs::File::open(&self.path)?);
+        file.seek(SeekFrom::Start(self.fences[fence - 1].1))?;
+
+        for _ in 0..self.fence_interval {
+            match read_entry::<I, _>(&mut file)? {
+                Some((entry_id, page_number)) if entry_id == *id => return Ok(Some(page_number)),
+                Some((entry_id, _)) if entry_id < *id => continue,
+                _ => break,
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Sorted runs spilled to disk while building the index.
+    pub fn runs(&self) -> usize {
+        self.runs
+    }
+}
+
+/// Merges sorted run files, calling `emit` with every entry in order of id then page.
+fn merge_runs<I: Ord + DeserializeOwned>(
+    paths: &[String],
+    mut emit: impl FnMut(I, u64) -> Result<(), CollectionFileError>,
+) -> Result<(), CollectionFileError> {
+    let mut readers = paths
+        .iter()
+        .map(|path| Ok(BufReader::new(fs::File::open(path)?)))
+        .collect::<Result<Vec<_>, CollectionFileError>>()?;
+    let mut heap = BinaryHeap::new();
+    for (run, reader) in readers.iter_mut().enumerate() {
+        if let Some((id, page_number)) = read_entry(reader)? {
+            heap.push(Reverse((id, page_number, run)));
+        }
+    }
+
+    while let Some(Reverse((id, page_number, run))) = heap.pop() {
+        if let Some(next) = read_entry(&mut readers[run])? {
+            heap.push(Reverse((next.0, next.1, run)));
+        }
+        emit(id, page_number)?;
+    }
+
+    Ok(())
+}
+
+/// Builds a `SortedIndex` holding at most `memory_budget` entries in memory: entries are
+/// sorted and spilled to run files every `memory_budget` documents, then the runs are
+/// merged `SORTED_INDEX_MERGE_FAN_IN` at a time until one pass can write the index file.
+/// The fences kept in memory are capped at `memory_budget` too. When an id is on several
+/// pages the last page wins, like `index_collection_id`.
+pub fn build_sorted_index<T: Document>(
+    collection_file: &CollectionFile<T>,
+    memory_budget: usize,
+) -> Result<SortedIndex<<T as HasId>::Id>, CollectionFileError>
+where
+    <T as HasId>::Id: Ord + Serialize + DeserializeOwned,
+{
+    let memory_budget = memory_budget.max(1);
+    let run_path = |run: usize| format!("{}.run{}", collection_file.index_sidecar_path(), run);
+    let mut buffer = Vec::with_capacity(memory_budget);
+    let mut runs = 0;
+    let mut entries: u64 = 0;
+
+    let mut spill = |buffer: &mut Vec<(<T as HasId>::Id, u64)>| {
+        buffer.sort();
+        let mut file = BufWriter::new(fs::File::create(run_path(runs))?);
+        for (id, page_number) in buffer.drain(..) {
+            write_entry(&mut file, &id, page_number)?;
+        }
+        file.flush()?;
+        runs += 1;
+
+        Ok::<(), CollectionFileError>(())
+    };
+
+    for page_number in 0..collection_file.number_of_pages() {
+        for document in collection_file.read_page(page_number)?.documents().iter() {
+            buffer.push((document.id(), page_number));
+            entries += 1;
+            if buffer.len() >= memory_budget {
+                spill(&mut buffer)?;
+            }
+        }
+    }
+    if !buffer.is_empty() {
+        spill(&mut buffer)?;
+    }
+
+    // Intermediate passes keep every entry, duplicates are only dropped in the last one.
+    let mut pending_runs: Vec<String> = (0..runs).map(run_path).collect();
+    let mut next_run = runs;
+    while pending_runs.len() > SORTED_INDEX_MERGE_FAN_IN {
+        let mut merged_runs = vec![];
+        for group in pending_runs.chunks(SORTED_INDEX_MERGE_FAN_IN) {
+            let path = run_path(next_run);
+            next_run += 1;
+
+            let mut file = BufWriter::new(fs::File::create(&path)?);
+            merge_runs::<<T as HasId>::Id>(group, |id, page_number| {
+                write_entry(&mut file, &id, page_number)?;
+                Ok(())
+            })?;
+            file.flush()?;
+
+            for run in group {
+                fs::remove_file(run)?;
+            }
+            merged_runs.push(path);
+        }
+        pending_runs = merged_runs;
+    }
+
+    let path = format!(
+        "{}/{}.sorted_index",
+        collection_file.dir(),
+        collection_file.name()
+    );
+    let mut file = BufWriter::new(fs::File::create(&path)?);
+    let fence_interval = SORTED_INDEX_FENCE_INTERVAL.max(entries.div_ceil(memory_budget as u64));
+    let mut fences = vec![];
+    let mut offset = 0;
+    let mut len = 0;
+    let mut pending: Option<(<T as HasId>::Id, u64)> = None;
+
+    let mut push_entry = |id, page_number| {
+        if len % fence_interval == 0 {
+            fences.push((id, offset));
+        }
+        offset += write_entry(&mut file, &id, page_number)?;
+        len += 1;
+
+        Ok::<(), CollectionFileError>(())
+    };
+
+    merge_runs(&pending_runs, |id, page_number| {
+        match pending.as_mut() {
+            // Entries come by id then page, the last one of an id has its highest page.
+            Some(pending) if pending.0 == id => pending.1 = page_number,
+            _ => {
+                if let Some((pending_id, pending_page_number)) = pending.replace((id, page_number))
+                {
+                    push_entry(pending_id, pending_page_number)?;
+                }
+            }
+        }
+
+        Ok(())
+    })?;
+    if let Some((pending_id, pending_page_number)) = pending {
+        push_entry(pending_id, pending_page_number)?;
+    }
+    file.flush()?;
+
+    for run in pending_runs.iter() {
+        fs::remove_file(run)?;
+    }
+
+    Ok(SortedIndex {
+        path,
+        fences,
+        fence_interval,
+        len,
+        runs,
+    })
+}
 
 pub fn index_collection_id<T: Document>(
     collection_file: &CollectionFile<T>,
 ) -> Result<IdToPageMap<T>, CollectionFileError> {
-    let mut collection_index = HashMap::<<T>::Id, u64>::new();
+    index_collection_id_with_hasher(collection_file)
+}
+
+/// Pages read by `index_collection_id_with_hasher`: every data page, empty ones included.
+pub fn index_build_plan<T: Document>(collection_file: &CollectionFile<T>) -> Vec<u64> {
+    (0..collection_file.number_of_pages()).collect()
+}
+
+pub fn index_collection_id_with_hasher<T: Document, S: BuildHasher + Default>(
+    collection_file: &CollectionFile<T>,
+) -> Result<IdToPageMap<T, S>, CollectionFileError> {
+    let mut collection_index = HashMap::<<T>::Id, u64, S>::default();
     println!("{:?}", collection_file);
 
-    for i in 0..collection_file.number_of_pages() {
+    for i in index_build_plan(collection_file) {
         let page = collection_file.read_page(i)?;
         println!("{:?}", page);
 
@@ -127,4 +495,128 @@ mod tests {
 
         assert_eq!(index_hash_map, expected_hash_map)
     }
+
+    #[cfg(feature = "fxhash")]
+    #[test]
+    fn test_fxhash_index_matches_siphash_index() {
+        use std::collections::hash_map::RandomState;
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+
+        let mut collection_file = CollectionFile::<MyDocument>::new("test", dir_name).unwrap();
+        let mut collection_page = collection_file.read_page(0).unwrap();
+
+        for id in 0..50_000 {
+            if collection_page.insert_document(&MyDocument { id }).is_err() {
+                collection_file.write_page(&collection_page).unwrap();
+                collection_page = collection_file.new_page(collection_page.get_page_number() + 1);
+                collection_page.insert_document(&MyDocument { id }).unwrap();
+            }
+        }
+        collection_file.write_page(&collection_page).unwrap();
+
+        let sip_index =
+            index_collection_id_with_hasher::<_, RandomState>(&collection_file).unwrap();
+        let fx_index =
+            index_collection_id_with_hasher::<_, rustc_hash::FxBuildHasher>(&collection_file)
+                .unwrap();
+
+        assert_eq!(sip_index.len(), 50_000);
+        assert_eq!(fx_index.len(), 50_000);
+        for id in 0..50_000 {
+            assert_eq!(sip_index.get(&id), fx_index.get(&id));
+        }
+    }
+
+    #[test]
+    fn test_build_sorted_index_spills_runs() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+
+        let mut collection_file = CollectionFile::<MyDocument>::new("test", dir_name).unwrap();
+
+        // Ids inserted in reverse over 5 pages, 500 documents in all.
+        for page_number in 0..5 {
+            let mut page = collection_file.new_page(page_number);
+            for i in 0..100 {
+                page.insert_document(&MyDocument {
+                    id: 1_000 - (page_number * 100 + i) * 2,
+                })
+                .unwrap();
+            }
+            collection_file.write_page(&page).unwrap();
+        }
+
+        let sorted_index = build_sorted_index(&collection_file, 32).unwrap();
+        let expected = index_collection_id(&collection_file).unwrap();
+
+        assert_eq!(sorted_index.runs(), 16);
+        assert_eq!(sorted_index.len(), 500);
+        for (id, page_number) in expected.iter() {
+            assert_eq!(sorted_index.get(id).unwrap(), Some(*page_number));
+        }
+        assert_eq!(sorted_index.get(&0).unwrap(), None);
+        assert_eq!(sorted_index.get(&3).unwrap(), None);
+        assert_eq!(sorted_index.get(&2_000).unwrap(), None);
+        assert!(
+            !std::path::Path::new(&format!("{}.run0", collection_file.index_sidecar_path()))
+                .exists()
+        );
+    }
+
+    #[test]
+    fn test_build_sorted_index_merges_in_passes() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+
+        let mut collection_file = CollectionFile::<MyDocument>::new("test", dir_name).unwrap();
+
+        // 500 documents, plus id 1_000 on pages 4 and 1, the last page wins.
+        for page_number in 0..5 {
+            let mut page = collection_file.new_page(page_number);
+            for i in 0..100 {
+                page.insert_document(&MyDocument {
+                    id: (i * 5 + page_number) * 3,
+                })
+                .unwrap();
+            }
+            collection_file.write_page(&page).unwrap();
+        }
+        let mut page = collection_file.read_page(4).unwrap();
+        page.insert_document(&MyDocument { id: 1_000 }).unwrap();
+        collection_file.write_page(&page).unwrap();
+        let mut page = collection_file.read_page(1).unwrap();
+        page.insert_document(&MyDocument { id: 1_000 }).unwrap();
+        collection_file.write_page(&page).unwrap();
+
+        // 251 runs, more than one merge pass can take.
+        let sorted_index = build_sorted_index(&collection_file, 2).unwrap();
+        let expected = index_collection_id(&collection_file).unwrap();
+
+        assert_eq!(sorted_index.runs(), 251);
+        assert_eq!(sorted_index.len(), 501);
+        assert!(!sorted_index.is_empty());
+        assert!(sorted_index.fences.len() <= 2);
+        for (id, page_number) in expected.iter() {
+            assert_eq!(sorted_index.get(id).unwrap(), Some(*page_number));
+        }
+        assert_eq!(sorted_index.get(&1_000).unwrap(), Some(4));
+        assert_eq!(sorted_index.get(&1).unwrap(), None);
+        assert_eq!(
+            fs::read_dir(dir_name)
+                .unwrap()
+                .filter(|entry| entry
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .contains(".run"))
+                .count(),
+            0
+        );
+    }
 }
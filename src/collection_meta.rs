@@ -0,0 +1,46 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+
+use crate::collection_file::CollectionFileError;
+
+/// Small application metadata kept next to a collection in `{dir}/{name}.meta`. The whole
+/// map is rewritten on every `set`, through a temporary file renamed over the old one.
+#[derive(Debug)]
+pub struct MetaStore {
+    path: String,
+    entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl MetaStore {
+    pub fn open(name: &str, dir: &str) -> Result<Self, CollectionFileError> {
+        let path = format!("{}/{}.meta", dir, name);
+        let entries = match fs::read(&path) {
+            Ok(encoded) => bincode::deserialize(&encoded)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(CollectionFileError::FileError(e)),
+        };
+
+        Ok(MetaStore { path, entries })
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.entries.get(key).cloned()
+    }
+
+    pub fn set(&mut self, key: &str, value: &[u8]) -> Result<(), CollectionFileError> {
+        let mut entries = self.entries.clone();
+        entries.insert(key.to_string(), value.to_vec());
+        let encoded = bincode::serialize(&entries)?;
+
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&encoded)?;
+        file.sync_data()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        self.entries = entries;
+
+        Ok(())
+    }
+}
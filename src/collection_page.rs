@@ -1,23 +1,55 @@
 use crate::document::{Document, HasId};
+use crate::serialization::SerializationConfig;
 use bincode::ErrorKind;
+use std::hash::{Hash, Hasher};
 
 use serde::{Deserialize, Serialize};
 
 pub const COLLECTION_PAGE_SIZE: u64 = 64_000;
-pub const COLLECTION_PAGE_HEADER_SIZE: u64 = std::mem::size_of::<CollectionPageHeader>() as u64;
+// The header is followed by the optional slot directory (a tag and a length prefix)
+// and the length prefix of the documents vector.
+pub const COLLECTION_PAGE_HEADER_SIZE: u64 = (std::mem::size_of::<CollectionPageHeader>()
+    + std::mem::size_of::<u8>()
+    + 2 * std::mem::size_of::<u64>()) as u64;
 pub const COLLECTION_PAGE_DATA_SIZE: u64 = COLLECTION_PAGE_SIZE - COLLECTION_PAGE_HEADER_SIZE;
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct CollectionPageHeader {
     page_number: u64,
     number_of_documents: u64,
     free_space_available: u64,
+    schema_version: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+/// Entry of the slot directory: the hash of a document id and the index of the document.
+pub type Slot = (u64, u32);
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CollectionPage<T> {
     pub header: CollectionPageHeader,
+    // Sorted by id hash, so `find_document` can binary search instead of scanning.
+    slots: Option<Vec<Slot>>,
     documents: Vec<T>,
+    // Last known size of each document, by index. Entries are None until the document is
+    // inserted or updated through the page, and the whole cache is dropped by `documents_mut`.
+    #[serde(skip)]
+    document_sizes: Vec<Option<u64>>,
+    #[serde(skip)]
+    serialization_config: SerializationConfig,
+    #[serde(skip)]
+    collection_id: Option<u64>,
+    #[serde(skip, default = "default_page_size")]
+    page_size: u64,
+}
+
+fn default_page_size() -> u64 {
+    COLLECTION_PAGE_SIZE
+}
+
+impl<T: PartialEq> PartialEq for CollectionPage<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.header == other.header && self.documents == other.documents
+    }
 }
 
 #[derive(Debug)]
@@ -32,7 +64,48 @@ impl From<Box<ErrorKind>> for CollectionPageError {
     }
 }
 
+/// FNV-1a, stable across processes and Rust versions unlike the std hashers, as the
+/// hashes of the slot directory are persisted.
+pub struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        FnvHasher(0xcbf29ce484222325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+/// How many documents of the size of `sample` fit in a page encoded with the default
+/// serialization config, to estimate page counts before loading documents.
+pub fn docs_per_page<T: Document>(sample: &T) -> Result<u64, CollectionPageError> {
+    let page = CollectionPage::<T>::new(0);
+
+    Ok(page.data_size() / page.document_size(sample)?.max(1))
+}
+
+fn id_hash<I: Hash>(id: &I) -> u64 {
+    let mut hasher = FnvHasher::default();
+    id.hash(&mut hasher);
+    hasher.finish()
+}
+
 impl CollectionPageHeader {
+    pub fn page_number(&self) -> u64 {
+        self.page_number
+    }
+
     pub fn number_of_documents(&self) -> u64 {
         return self.number_of_documents;
     }
@@ -40,26 +113,164 @@ impl CollectionPageHeader {
     pub fn space_available(&self) -> u64 {
         return self.free_space_available;
     }
+
+    pub fn schema_version(&self) -> u32 {
+        self.schema_version
+    }
+}
+
+impl<T> CollectionPage<T> {
+    /// Takes the documents out of a page, which may have been decoded as a partial view.
+    pub fn into_documents(self) -> Vec<T> {
+        self.documents
+    }
 }
 
 impl<T: Document> CollectionPage<T> {
     pub fn new(page_number: u64) -> CollectionPage<T> {
-        CollectionPage {
+        Self::with_serialization_config(page_number, SerializationConfig::default())
+    }
+
+    pub fn with_serialization_config(
+        page_number: u64,
+        serialization_config: SerializationConfig,
+    ) -> CollectionPage<T> {
+        Self::with_page_size(page_number, serialization_config, COLLECTION_PAGE_SIZE)
+    }
+
+    pub fn with_page_size(
+        page_number: u64,
+        serialization_config: SerializationConfig,
+        page_size: u64,
+    ) -> CollectionPage<T> {
+        let mut page = CollectionPage {
             header: CollectionPageHeader {
                 page_number,
                 number_of_documents: 0,
-                free_space_available: COLLECTION_PAGE_DATA_SIZE,
+                free_space_available: 0,
+                schema_version: T::schema_version(),
             },
+            slots: None,
             documents: vec![],
+            document_sizes: vec![],
+            serialization_config,
+            collection_id: None,
+            page_size,
+        };
+        page.header.free_space_available = page.data_size();
+
+        page
+    }
+
+    /// Bytes of the page available to documents.
+    pub fn data_size(&self) -> u64 {
+        self.page_size
+            .saturating_sub(COLLECTION_PAGE_HEADER_SIZE + self.serialization_config.page_overhead())
+    }
+
+    /// Size of the page in the file it was read from, the header is left untouched.
+    pub fn set_page_size(&mut self, page_size: u64) {
+        self.page_size = page_size;
+    }
+
+    /// Starts maintaining a slot directory, must be called while the page is empty.
+    pub fn enable_slot_directory(&mut self) {
+        if self.slots.is_none() && self.documents.is_empty() {
+            self.slots = Some(vec![]);
+        }
+    }
+
+    pub fn has_slot_directory(&self) -> bool {
+        self.slots.is_some()
+    }
+
+    /// Space taken by a slot directory entry, the largest possible entry is counted as
+    /// variable size encodings depend on the values.
+    fn slot_size(&self) -> Result<u64, CollectionPageError> {
+        if self.slots.is_none() {
+            return Ok(0);
+        }
+
+        Ok(self
+            .serialization_config
+            .serialized_size(&(u64::MAX, u32::MAX))?
+            + self.serialization_config.document_separator_size())
+    }
+
+    fn insert_slot(&mut self, hash: u64, index: usize) {
+        if let Some(slots) = self.slots.as_mut() {
+            let position = slots.partition_point(|slot| *slot < (hash, index as u32));
+            slots.insert(position, (hash, index as u32));
         }
     }
 
+    fn remove_slot(&mut self, hash: u64, index: usize) {
+        if let Some(slots) = self.slots.as_mut() {
+            slots.retain(|slot| *slot != (hash, index as u32));
+        }
+    }
+
+    fn find_index(&self, id: <T as HasId>::Id) -> Option<usize> {
+        let slots = match self.slots.as_ref() {
+            Some(slots) => slots,
+            None => return self.documents.iter().position(|d| d.id() == id),
+        };
+
+        let hash = id_hash(&id);
+        let start = slots.partition_point(|(slot_hash, _)| *slot_hash < hash);
+
+        slots[start..]
+            .iter()
+            .take_while(|(slot_hash, _)| *slot_hash == hash)
+            .map(|(_, index)| *index as usize)
+            .find(|index| self.documents[*index].id() == id)
+    }
+
+    /// Space a document takes in the page, including what separates it from the others.
+    fn document_size(&self, document: &T) -> Result<u64, CollectionPageError> {
+        Ok(self.serialization_config.serialized_size(document)?
+            + self.serialization_config.document_separator_size())
+    }
+
+    /// Size of the document at `index`, from the cache when it is known.
+    fn cached_document_size(&self, index: usize) -> Result<u64, CollectionPageError> {
+        match self.document_sizes.get(index).copied().flatten() {
+            Some(size) => Ok(size),
+            None => self.document_size(&self.documents[index]),
+        }
+    }
+
+    /// Keeps the size cache as long as the documents, pages decoded from a file start with
+    /// an empty one.
+    fn align_document_sizes(&mut self) {
+        self.document_sizes.resize(self.documents.len(), None);
+    }
+
+    pub fn set_serialization_config(&mut self, serialization_config: SerializationConfig) {
+        self.document_sizes.clear();
+        self.serialization_config = serialization_config;
+    }
+
+    /// Id of the collection file the page was created for or read from, if any.
+    pub fn collection_id(&self) -> Option<u64> {
+        self.collection_id
+    }
+
+    pub fn set_collection_id(&mut self, collection_id: u64) {
+        self.collection_id = Some(collection_id);
+    }
+
+    pub fn header(&self) -> &CollectionPageHeader {
+        &self.header
+    }
+
     pub fn get_page_number(&self) -> u64 {
         self.header.page_number
     }
 
     pub fn insert_document(&mut self, document: &T) -> Result<(), CollectionPageError> {
-        let document_size = bincode::serialized_size(&document)?;
+        let serialized_size = self.document_size(document)?;
+        let document_size = serialized_size + self.slot_size()?;
 
         println!("Document size: {:?}", document_size);
         println!(
@@ -67,55 +278,163 @@ impl<T: Document> CollectionPage<T> {
             self.header.free_space_available
         );
 
-        if self.header.free_space_available < document_size as u64 {
+        if self.header.free_space_available < document_size {
             return Err(CollectionPageError::NoFreeSpaceAvailable);
         }
 
+        self.align_document_sizes();
+        self.insert_slot(id_hash(&document.id()), self.documents.len());
         self.documents.push(document.clone());
+        self.document_sizes.push(Some(serialized_size));
 
-        self.header.free_space_available -= document_size as u64;
+        self.header.free_space_available -= document_size;
         self.header.number_of_documents += 1;
 
         Ok(())
     }
 
+    /// Inserts documents from the front of `docs` until the page is full. Inserted documents
+    /// are removed from `docs`, the others are left for the next page.
+    pub fn try_insert_all(&mut self, docs: &mut Vec<T>) -> Result<(), CollectionPageError> {
+        let mut inserted = 0;
+
+        for document in docs.iter() {
+            match self.insert_document(document) {
+                Ok(_) => inserted += 1,
+                Err(CollectionPageError::NoFreeSpaceAvailable) => break,
+                Err(e) => {
+                    docs.drain(..inserted);
+                    return Err(e);
+                }
+            }
+        }
+
+        docs.drain(..inserted);
+
+        Ok(())
+    }
+
     pub fn find_document(&self, id: <T as HasId>::Id) -> Option<T> {
-        self.documents.iter().find(|d| d.id() == id).cloned()
+        self.find_index(id)
+            .map(|index| self.documents[index].clone())
     }
 
     pub fn documents(&self) -> &Vec<T> {
         &self.documents
     }
 
+    /// Documents for in place edits, `defragment` must be called afterwards to bring the
+    /// header and slot directory up to date.
+    pub fn documents_mut(&mut self) -> &mut Vec<T> {
+        self.document_sizes.clear();
+        &mut self.documents
+    }
+
+    /// Id, start offset and length of each document within the serialized documents, which
+    /// start right after the length prefix (or opening bracket) of the documents vector.
+    pub fn document_offsets(
+        &self,
+    ) -> Result<Vec<(<T as HasId>::Id, usize, usize)>, CollectionPageError> {
+        let separator_size = self.serialization_config.document_separator_size();
+        let mut offsets = Vec::with_capacity(self.documents.len());
+        let mut start = 0;
+
+        for (index, document) in self.documents.iter().enumerate() {
+            let size = self.cached_document_size(index)?;
+            offsets.push((
+                document.id(),
+                start as usize,
+                (size - separator_size) as usize,
+            ));
+            start += size;
+        }
+
+        Ok(offsets)
+    }
+
     pub fn update_document(&mut self, new_doc: &T) -> Result<(), CollectionPageError> {
-        for (index, value) in self.documents.iter().enumerate() {
-            if value.id() == new_doc.id() {
-                let old_version_size = bincode::serialized_size(&value)?;
-                let new_vesion_size = bincode::serialized_size(&new_doc)?;
-
-                if self.header.free_space_available - old_version_size + new_vesion_size
-                    > COLLECTION_PAGE_DATA_SIZE
-                {
-                    return Err(CollectionPageError::NoFreeSpaceAvailable);
-                }
+        let index = self
+            .find_index(new_doc.id())
+            .ok_or(CollectionPageError::DocumentNotFound)?;
+
+        // Sizes are computed before any change, a serialization error leaves the page as is.
+        let old_version_size = self.cached_document_size(index)?;
+        let new_vesion_size = self.document_size(new_doc)?;
+
+        if new_vesion_size > self.header.free_space_available + old_version_size {
+            return Err(CollectionPageError::NoFreeSpaceAvailable);
+        }
 
-                self.header.free_space_available -= old_version_size + new_vesion_size;
+        self.header.free_space_available =
+            self.header.free_space_available + old_version_size - new_vesion_size;
 
-                self.documents[index] = new_doc.clone();
+        self.documents[index] = new_doc.clone();
+        self.align_document_sizes();
+        self.document_sizes[index] = Some(new_vesion_size);
 
-                return Ok(());
+        Ok(())
+    }
+
+    /// Recomputes the header from the live documents, returning the number of bytes
+    /// reclaimed from space that was still accounted as used.
+    pub fn defragment(&mut self) -> Result<u64, CollectionPageError> {
+        let used_space = self.used_space()?;
+
+        if used_space > self.data_size() {
+            return Err(CollectionPageError::NoFreeSpaceAvailable);
+        }
+
+        let free_space_available = self.data_size() - used_space;
+        let reclaimed = free_space_available.saturating_sub(self.header.free_space_available);
+
+        self.header.free_space_available = free_space_available;
+        self.header.number_of_documents = self.documents.len() as u64;
+
+        if let Some(slots) = self.slots.as_mut() {
+            slots.clear();
+            for index in 0..self.documents.len() {
+                self.insert_slot(id_hash(&self.documents[index].id()), index);
             }
         }
-        return Err(CollectionPageError::DocumentNotFound);
+
+        Ok(reclaimed)
+    }
+
+    /// Free space computed from the live documents, which the header should agree with.
+    pub fn recomputed_space_available(&self) -> Result<u64, CollectionPageError> {
+        Ok(self.data_size().saturating_sub(self.used_space()?))
+    }
+
+    fn used_space(&self) -> Result<u64, CollectionPageError> {
+        let mut used_space = 0;
+        for index in 0..self.documents.len() {
+            used_space += self.cached_document_size(index)? + self.slot_size()?;
+        }
+
+        Ok(used_space)
     }
 
     pub fn remove_document(&mut self, id: <T as HasId>::Id) -> Result<T, CollectionPageError> {
         let index = self
-            .documents
-            .iter()
-            .position(|e| e.id() == id)
+            .find_index(id)
             .ok_or_else(|| CollectionPageError::DocumentNotFound)?;
 
+        let document_size = self.cached_document_size(index)? + self.slot_size()?;
+
+        self.header.free_space_available += document_size;
+        self.header.number_of_documents -= 1;
+
+        // swap_remove moves the last document into the freed slot.
+        let last_index = self.documents.len() - 1;
+        self.remove_slot(id_hash(&id), index);
+        if index != last_index {
+            let moved_hash = id_hash(&self.documents[last_index].id());
+            self.remove_slot(moved_hash, last_index);
+            self.insert_slot(moved_hash, index);
+        }
+
+        self.align_document_sizes();
+        self.document_sizes.swap_remove(index);
         Ok(self.documents.swap_remove(index))
     }
 }
@@ -243,6 +562,54 @@ mod tests {
         )
     }
 
+    #[test]
+    fn update_one_document_accounts_for_size_change() {
+        #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+        struct UserDocument {
+            id: u64,
+            name: String,
+        }
+
+        impl HasId for UserDocument {
+            type Id = u64;
+
+            fn id(&self) -> u64 {
+                self.id
+            }
+        }
+
+        let mut collection_page = CollectionPage::<UserDocument>::new(0);
+
+        collection_page
+            .insert_document(&UserDocument {
+                id: 1,
+                name: "lol".to_string(),
+            })
+            .unwrap();
+        collection_page
+            .update_document(&UserDocument {
+                id: 1,
+                name: "a".repeat(100),
+            })
+            .unwrap();
+
+        assert_eq!(
+            collection_page.header.free_space_available,
+            COLLECTION_PAGE_DATA_SIZE - (8 + 8 + 100)
+        );
+
+        let too_big = collection_page.update_document(&UserDocument {
+            id: 1,
+            name: "a".repeat(COLLECTION_PAGE_DATA_SIZE as usize),
+        });
+
+        assert!(matches!(
+            too_big,
+            Err(CollectionPageError::NoFreeSpaceAvailable)
+        ));
+        assert_eq!(collection_page.documents[0].name, "a".repeat(100));
+    }
+
     #[test]
     fn delete_one_document() {
         #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
@@ -270,4 +637,325 @@ mod tests {
 
         assert_eq!(collection_page.documents, vec![])
     }
+
+    #[test]
+    fn delete_one_document_frees_space() {
+        let mut collection_page = CollectionPage::<MyDocument>::new(0);
+
+        collection_page
+            .insert_document(&MyDocument { id: 1 })
+            .unwrap();
+        collection_page
+            .insert_document(&MyDocument { id: 2 })
+            .unwrap();
+        collection_page.remove_document(1).unwrap();
+
+        assert_eq!(collection_page.header.number_of_documents, 1);
+        assert_eq!(
+            collection_page.header.free_space_available,
+            COLLECTION_PAGE_DATA_SIZE - 8
+        )
+    }
+
+    #[test]
+    fn defragment_reclaims_leaked_space() {
+        let mut collection_page = CollectionPage::<MyDocument>::new(0);
+
+        for id in 0..3 {
+            collection_page.insert_document(&MyDocument { id }).unwrap();
+        }
+        collection_page.remove_document(1).unwrap();
+        // Simulates a page written before removals credited their space back.
+        collection_page.header.free_space_available -= 8;
+        collection_page.header.number_of_documents += 1;
+
+        let reclaimed = collection_page.defragment().unwrap();
+
+        assert_eq!(reclaimed, 8);
+        assert_eq!(collection_page.header.number_of_documents, 2);
+        assert_eq!(
+            collection_page.header.free_space_available,
+            COLLECTION_PAGE_DATA_SIZE - 8 * 2
+        );
+        assert_eq!(
+            collection_page.documents,
+            vec![MyDocument { id: 0 }, MyDocument { id: 2 }]
+        );
+        assert_eq!(collection_page.defragment().unwrap(), 0);
+    }
+
+    #[test]
+    fn slot_directory_lookups_do_not_scan_the_page() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static ID_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+        struct CountedDocument {
+            id: u64,
+        }
+
+        impl HasId for CountedDocument {
+            type Id = u64;
+
+            fn id(&self) -> u64 {
+                ID_CALLS.fetch_add(1, Ordering::Relaxed);
+                self.id
+            }
+        }
+
+        let mut collection_page = CollectionPage::<CountedDocument>::new(0);
+        collection_page.enable_slot_directory();
+
+        for id in 0..2_000 {
+            collection_page
+                .insert_document(&CountedDocument { id })
+                .unwrap();
+        }
+        collection_page.remove_document(10).unwrap();
+
+        ID_CALLS.store(0, Ordering::Relaxed);
+        assert_eq!(
+            collection_page.find_document(1_500),
+            Some(CountedDocument { id: 1_500 })
+        );
+        assert_eq!(collection_page.find_document(10), None);
+        assert!(ID_CALLS.load(Ordering::Relaxed) <= 2);
+
+        // The document moved into the slot of the removed one is still found.
+        assert_eq!(
+            collection_page.find_document(1_999),
+            Some(CountedDocument { id: 1_999 })
+        );
+        assert_eq!(
+            collection_page.header.free_space_available,
+            COLLECTION_PAGE_DATA_SIZE - 1_999 * (8 + 12)
+        );
+    }
+
+    #[test]
+    fn updates_do_not_reserialize_the_other_documents() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static SERIALIZE_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        #[derive(Deserialize, Clone, Debug, PartialEq)]
+        struct CountedDocument {
+            id: u64,
+            name: String,
+        }
+
+        impl Serialize for CountedDocument {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                SERIALIZE_CALLS.fetch_add(1, Ordering::Relaxed);
+                (self.id, &self.name).serialize(serializer)
+            }
+        }
+
+        impl HasId for CountedDocument {
+            type Id = u64;
+
+            fn id(&self) -> u64 {
+                self.id
+            }
+        }
+
+        let mut collection_page = CollectionPage::<CountedDocument>::new(0);
+        for id in 0..100 {
+            collection_page
+                .insert_document(&CountedDocument {
+                    id,
+                    name: "a".to_string(),
+                })
+                .unwrap();
+        }
+
+        SERIALIZE_CALLS.store(0, Ordering::Relaxed);
+        collection_page
+            .update_document(&CountedDocument {
+                id: 50,
+                name: "abc".to_string(),
+            })
+            .unwrap();
+        collection_page.remove_document(10).unwrap();
+        assert_eq!(collection_page.defragment().unwrap(), 0);
+
+        // Only the new version of the updated document is serialized.
+        assert_eq!(SERIALIZE_CALLS.load(Ordering::Relaxed), 1);
+        assert_eq!(
+            collection_page.header.free_space_available,
+            COLLECTION_PAGE_DATA_SIZE - 98 * 17 - 19
+        );
+
+        // Edits through `documents_mut` drop the cache, sizes are computed again.
+        collection_page.documents_mut()[0].name = "abcd".to_string();
+        SERIALIZE_CALLS.store(0, Ordering::Relaxed);
+        collection_page.defragment().unwrap();
+        assert_eq!(SERIALIZE_CALLS.load(Ordering::Relaxed), 99);
+        assert_eq!(
+            collection_page.header.free_space_available,
+            COLLECTION_PAGE_DATA_SIZE - 97 * 17 - 19 - 20
+        );
+    }
+
+    #[test]
+    fn document_offsets_delimit_each_document() {
+        use crate::serialization::Codec;
+
+        #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+        struct NamedDocument {
+            id: u64,
+            name: String,
+        }
+
+        impl HasId for NamedDocument {
+            type Id = u64;
+
+            fn id(&self) -> u64 {
+                self.id
+            }
+        }
+
+        // The bincode documents follow an 8 bytes length prefix, the JSON ones are enclosed
+        // in brackets.
+        for (config, region_start, region_end) in [
+            (SerializationConfig::default(), 8, 0),
+            (SerializationConfig::default().with_codec(Codec::Json), 1, 1),
+        ] {
+            let mut collection_page =
+                CollectionPage::<NamedDocument>::with_serialization_config(0, config);
+            for id in 0..5 {
+                collection_page
+                    .insert_document(&NamedDocument {
+                        id,
+                        name: "a".repeat(id as usize * 3),
+                    })
+                    .unwrap();
+            }
+
+            let encoded = config.serialize(collection_page.documents()).unwrap();
+            let region = &encoded[region_start..encoded.len() - region_end];
+            let offsets = collection_page.document_offsets().unwrap();
+
+            assert_eq!(offsets.len(), 5);
+            assert_eq!(offsets[4].1 + offsets[4].2, region.len());
+            for ((id, start, length), document) in
+                offsets.iter().zip(collection_page.documents().iter())
+            {
+                assert_eq!(*id, document.id);
+                let decoded: NamedDocument = config
+                    .deserialize(&region[*start..*start + *length])
+                    .unwrap();
+                assert_eq!(&decoded, document);
+            }
+        }
+    }
+
+    #[test]
+    fn docs_per_page_matches_inserts_until_full() {
+        let sample = MyDocument { id: 0 };
+        let capacity = docs_per_page(&sample).unwrap();
+        assert_eq!(capacity, COLLECTION_PAGE_DATA_SIZE / 8);
+
+        let mut collection_page = CollectionPage::<MyDocument>::new(0);
+        let mut inserted = 0;
+        while collection_page
+            .insert_document(&MyDocument { id: inserted })
+            .is_ok()
+        {
+            inserted += 1;
+        }
+
+        assert_eq!(inserted, capacity);
+    }
+
+    #[test]
+    fn try_insert_all_takes_as_many_documents_as_fit() {
+        let mut collection_page = CollectionPage::<MyDocument>::new(0);
+        let capacity = COLLECTION_PAGE_DATA_SIZE / 8;
+        let mut documents: Vec<MyDocument> =
+            (0..capacity + 10).map(|id| MyDocument { id }).collect();
+
+        collection_page.try_insert_all(&mut documents).unwrap();
+
+        assert_eq!(collection_page.header.number_of_documents, capacity);
+        assert_eq!(
+            collection_page.header.free_space_available,
+            COLLECTION_PAGE_DATA_SIZE % 8
+        );
+        assert_eq!(
+            documents,
+            (capacity..capacity + 10)
+                .map(|id| MyDocument { id })
+                .collect::<Vec<MyDocument>>()
+        );
+    }
+
+    #[test]
+    fn header_tracks_inserts_and_removes() {
+        let mut collection_page = CollectionPage::<MyDocument>::new(3);
+
+        for id in 0..3 {
+            collection_page.insert_document(&MyDocument { id }).unwrap();
+        }
+        assert_eq!(collection_page.header().number_of_documents(), 3);
+
+        collection_page.remove_document(1).unwrap();
+
+        let header = collection_page.header();
+        assert_eq!(header.number_of_documents(), 2);
+        assert_eq!(header.space_available(), COLLECTION_PAGE_DATA_SIZE - 8 * 2);
+        assert_eq!(header.schema_version(), 0);
+    }
+
+    #[test]
+    fn serialization_failure_leaves_page_unchanged() {
+        #[derive(Deserialize, Clone, Debug, PartialEq)]
+        struct FailingDocument {
+            id: u64,
+            fails: bool,
+        }
+
+        impl Serialize for FailingDocument {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                if self.fails {
+                    return Err(serde::ser::Error::custom("document can't be serialized"));
+                }
+
+                (self.id, self.fails).serialize(serializer)
+            }
+        }
+
+        impl HasId for FailingDocument {
+            type Id = u64;
+
+            fn id(&self) -> u64 {
+                self.id
+            }
+        }
+
+        let mut collection_page = CollectionPage::<FailingDocument>::new(0);
+        let stored = FailingDocument {
+            id: 1,
+            fails: false,
+        };
+        let failing = FailingDocument { id: 1, fails: true };
+        collection_page.insert_document(&stored).unwrap();
+
+        assert!(matches!(
+            collection_page.update_document(&failing),
+            Err(CollectionPageError::SerializeError(_))
+        ));
+        assert!(matches!(
+            collection_page.insert_document(&FailingDocument { id: 2, fails: true }),
+            Err(CollectionPageError::SerializeError(_))
+        ));
+
+        assert_eq!(collection_page.documents, vec![stored]);
+        assert_eq!(collection_page.header.number_of_documents, 1);
+        assert_eq!(
+            collection_page.header.free_space_available,
+            COLLECTION_PAGE_DATA_SIZE - 9
+        );
+    }
 }
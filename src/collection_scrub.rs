@@ -0,0 +1,193 @@
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{DeserializeOwned, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+
+use crate::collection_page::{CollectionPageHeader, Slot};
+use crate::serialization::SerializationConfig;
+use bincode::ErrorKind;
+
+/// A document, or a whole page when `slot` is `None`, that could not be decoded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScrubFailure {
+    pub page_number: u64,
+    pub slot: Option<usize>,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScrubReport {
+    pub pages_scanned: u64,
+    pub documents_scanned: u64,
+    pub failures: Vec<ScrubFailure>,
+}
+
+impl ScrubReport {
+    pub fn is_clean(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Documents of a page decoded one by one. Documents are not delimited, so decoding stops
+/// at the first one that fails, its position and error are kept.
+struct ScrubbedDocuments<T> {
+    documents_read: u64,
+    failure: Option<(usize, String)>,
+    _marker: PhantomData<T>,
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for ScrubbedDocuments<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DocumentsVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: DeserializeOwned> Visitor<'de> for DocumentsVisitor<T> {
+            type Value = ScrubbedDocuments<T>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a sequence of documents")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut scrubbed = ScrubbedDocuments {
+                    documents_read: 0,
+                    failure: None,
+                    _marker: PhantomData,
+                };
+
+                loop {
+                    match seq.next_element::<T>() {
+                        Ok(Some(_)) => scrubbed.documents_read += 1,
+                        Ok(None) => break,
+                        Err(e) => {
+                            scrubbed.failure =
+                                Some((scrubbed.documents_read as usize, e.to_string()));
+                            break;
+                        }
+                    }
+                }
+
+                Ok(scrubbed)
+            }
+        }
+
+        deserializer.deserialize_seq(DocumentsVisitor(PhantomData))
+    }
+}
+
+// Same fields as `CollectionPage`, so it decodes the same bytes.
+#[derive(Deserialize)]
+struct ScrubbedPage<T: DeserializeOwned> {
+    #[allow(dead_code)]
+    header: CollectionPageHeader,
+    #[allow(dead_code)]
+    slots: Option<Vec<Slot>>,
+    #[serde(bound = "T: DeserializeOwned")]
+    documents: ScrubbedDocuments<T>,
+}
+
+/// Decodes the documents of an encoded page, returning how many were decoded and the
+/// failure that stopped decoding, if any.
+pub fn scrub_page<T: DeserializeOwned>(
+    serialization_config: &SerializationConfig,
+    page_number: u64,
+    encoded: &[u8],
+) -> (u64, Option<ScrubFailure>) {
+    match serialization_config.deserialize::<ScrubbedPage<T>>(encoded) {
+        Ok(page) => (
+            page.documents.documents_read,
+            page.documents.failure.map(|(slot, error)| ScrubFailure {
+                page_number,
+                slot: Some(slot),
+                error,
+            }),
+        ),
+        Err(e) => (
+            0,
+            Some(ScrubFailure {
+                page_number,
+                slot: None,
+                error: e.to_string(),
+            }),
+        ),
+    }
+}
+
+/// A document of a page that could not be decoded, by its position in the page.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DocError {
+    pub index: usize,
+    pub error: String,
+}
+
+/// Documents of a page decoded one by one, keeping those that decode. Decoding only goes on
+/// after a failure when the decoder counts the documents left, as bincode does, so each
+/// attempt moves forward. A failure that doesn't consume exactly its document's bytes makes
+/// the following documents fail too.
+struct SalvagedDocuments<T> {
+    documents: Vec<T>,
+    errors: Vec<DocError>,
+}
+
+impl<'de, T: DeserializeOwned> Deserialize<'de> for SalvagedDocuments<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DocumentsVisitor<T>(PhantomData<T>);
+
+        impl<'de, T: DeserializeOwned> Visitor<'de> for DocumentsVisitor<T> {
+            type Value = SalvagedDocuments<T>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a sequence of documents")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut salvaged = SalvagedDocuments {
+                    documents: vec![],
+                    errors: vec![],
+                };
+
+                for index in 0.. {
+                    let counted = seq.size_hint().is_some();
+                    match seq.next_element::<T>() {
+                        Ok(Some(document)) => salvaged.documents.push(document),
+                        Ok(None) => break,
+                        Err(e) => {
+                            salvaged.errors.push(DocError {
+                                index,
+                                error: e.to_string(),
+                            });
+                            if !counted {
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                Ok(salvaged)
+            }
+        }
+
+        deserializer.deserialize_seq(DocumentsVisitor(PhantomData))
+    }
+}
+
+#[derive(Deserialize)]
+struct SalvagedPage<T: DeserializeOwned> {
+    #[allow(dead_code)]
+    header: CollectionPageHeader,
+    #[allow(dead_code)]
+    slots: Option<Vec<Slot>>,
+    #[serde(bound = "T: DeserializeOwned")]
+    documents: SalvagedDocuments<T>,
+}
+
+/// Decodes the documents of an encoded page, skipping those that can't be decoded. Fails
+/// only when the page itself, its header for example, can't be decoded.
+pub fn salvage_page<T: DeserializeOwned>(
+    serialization_config: &SerializationConfig,
+    encoded: &[u8],
+) -> Result<(Vec<T>, Vec<DocError>), Box<ErrorKind>> {
+    let page = serialization_config.deserialize::<SalvagedPage<T>>(encoded)?;
+
+    Ok((page.documents.documents, page.documents.errors))
+}
@@ -0,0 +1,105 @@
+use std::fs;
+use std::io::Write;
+use std::ops::Range;
+
+use crate::collection_file::CollectionFileError;
+
+/// Next id to hand out to a collection allocating its own `u64` ids, kept in
+/// `{dir}/{name}.id_sequence`. Allocations hold an exclusive lock on
+/// `{dir}/{name}.id_sequence.lock` while they read and replace the counter, so handles in
+/// other threads or processes never get overlapping blocks.
+#[derive(Debug)]
+pub struct IdSequence {
+    path: String,
+}
+
+impl IdSequence {
+    pub fn open(name: &str, dir: &str) -> Self {
+        IdSequence {
+            path: format!("{}/{}.id_sequence", dir, name),
+        }
+    }
+
+    /// First id of the next block, 0 before anything was allocated.
+    pub fn next_id(&self) -> Result<u64, CollectionFileError> {
+        match fs::read(&self.path) {
+            Ok(encoded) => Ok(bincode::deserialize(&encoded)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(CollectionFileError::FileError(e)),
+        }
+    }
+
+    /// Reserves `n` consecutive ids. The new counter is written to a temporary file renamed
+    /// over the old one, a crash leaves either the old or the new counter.
+    pub fn allocate(&mut self, n: u64) -> Result<Range<u64>, CollectionFileError> {
+        // Released when `lock` is dropped, after the rename.
+        let lock = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(format!("{}.lock", self.path))?;
+        lock.lock()?;
+
+        let start = self.next_id()?;
+        let end = start
+            .checked_add(n)
+            .ok_or(CollectionFileError::IdSequenceOverflowError)?;
+
+        let tmp_path = format!("{}.tmp", self.path);
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(&bincode::serialize(&end)?)?;
+        file.sync_data()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        Ok(start..end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_concurrent_handles_get_disjoint_blocks() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap().to_string();
+
+        let allocators: Vec<_> = (0..8)
+            .map(|_| {
+                let dir_name = dir_name.clone();
+                thread::spawn(move || {
+                    let mut sequence = IdSequence::open("test", &dir_name);
+                    (0..50)
+                        .map(|_| sequence.allocate(3).unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+
+        let mut ids: Vec<u64> = allocators
+            .into_iter()
+            .flat_map(|allocator| allocator.join().unwrap())
+            .flatten()
+            .collect();
+        ids.sort();
+        assert_eq!(ids, (0..1_200).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn test_allocate_past_u64_max_is_rejected() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut sequence = IdSequence::open("test", dir_name);
+        fs::write(&sequence.path, bincode::serialize(&(u64::MAX - 2)).unwrap()).unwrap();
+
+        assert!(matches!(
+            sequence.allocate(5),
+            Err(CollectionFileError::IdSequenceOverflowError)
+        ));
+        assert_eq!(sequence.allocate(2).unwrap(), u64::MAX - 2..u64::MAX);
+    }
+}
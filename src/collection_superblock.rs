@@ -0,0 +1,102 @@
+use crate::document::{Document, HasId};
+use crate::serialization::SerializationConfig;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::SystemTime;
+
+/// Bytes reserved at the start of a collection file, pages are written after it.
+pub const SUPERBLOCK_SIZE: u64 = 4_096;
+const SUPERBLOCK_MAGIC: [u8; 8] = *b"RDBCOLL1";
+
+/// File level metadata. Always encoded with the default bincode options so it can be read
+/// before knowing how the rest of the file is encoded.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+pub struct CollectionSuperblock {
+    magic: [u8; 8],
+    collection_id: u64,
+    serialization_config: SerializationConfig,
+    page_size: u64,
+    id_type: IdTypeTag,
+}
+
+/// Type of the document ids a file is written with, and the schema version of the documents
+/// when it was recorded. Files created before it was recorded decode an empty tag.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct IdTypeTag {
+    name: String,
+    size: u64,
+    schema_version: u32,
+}
+
+impl IdTypeTag {
+    pub fn of<T: Document>() -> IdTypeTag {
+        IdTypeTag {
+            name: std::any::type_name::<<T as HasId>::Id>().to_string(),
+            size: std::mem::size_of::<<T as HasId>::Id>() as u64,
+            schema_version: T::schema_version(),
+        }
+    }
+
+    pub fn is_unknown(&self) -> bool {
+        self.name.is_empty()
+    }
+
+    pub fn same_id_type(&self, other: &IdTypeTag) -> bool {
+        self.name == other.name && self.size == other.size
+    }
+
+    pub fn schema_version(&self) -> u32 {
+        self.schema_version
+    }
+}
+
+fn random_collection_id() -> u64 {
+    RandomState::new().hash_one(SystemTime::now())
+}
+
+impl CollectionSuperblock {
+    pub fn new(
+        serialization_config: SerializationConfig,
+        page_size: u64,
+        id_type: IdTypeTag,
+    ) -> CollectionSuperblock {
+        CollectionSuperblock {
+            magic: SUPERBLOCK_MAGIC,
+            collection_id: random_collection_id(),
+            serialization_config,
+            page_size,
+            id_type,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.magic == SUPERBLOCK_MAGIC
+    }
+
+    /// Random identifier given to the file on creation, pages are tagged with it.
+    pub fn collection_id(&self) -> u64 {
+        self.collection_id
+    }
+
+    /// Gives the file a new random identifier, for a copy to be told apart from the original.
+    pub fn renew_collection_id(&mut self) {
+        self.collection_id = random_collection_id();
+    }
+
+    pub fn serialization_config(&self) -> SerializationConfig {
+        self.serialization_config
+    }
+
+    pub fn page_size(&self) -> u64 {
+        self.page_size
+    }
+
+    pub fn id_type(&self) -> &IdTypeTag {
+        &self.id_type
+    }
+
+    pub fn set_id_type(&mut self, id_type: IdTypeTag) {
+        self.id_type = id_type;
+    }
+}
@@ -0,0 +1,146 @@
+use std::io::{Read, Write};
+use std::marker::PhantomData;
+
+use bincode::ErrorKind;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::document::{Document, HasId};
+
+/// A document compressed on its own, so reading one document of a page only decompresses
+/// that one. The id stays uncompressed for lookups, store `Compressed<T>` in a collection
+/// and call `decompress` on the documents found.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(bound(
+    serialize = "<T as HasId>::Id: Serialize",
+    deserialize = "<T as HasId>::Id: DeserializeOwned"
+))]
+pub struct Compressed<T: HasId> {
+    id: <T as HasId>::Id,
+    // Unset when deflate didn't make the document smaller, `bytes` is then its plain encoding.
+    deflated: bool,
+    bytes: Vec<u8>,
+    #[serde(skip)]
+    _marker: PhantomData<T>,
+}
+
+impl<T: HasId> std::fmt::Debug for Compressed<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Compressed")
+            .field("deflated", &self.deflated)
+            .field("stored_len", &self.bytes.len())
+            .finish()
+    }
+}
+
+impl<T: HasId> HasId for Compressed<T> {
+    type Id = <T as HasId>::Id;
+
+    fn id(&self) -> Self::Id {
+        self.id
+    }
+
+    fn schema_version() -> u32 {
+        T::schema_version()
+    }
+}
+
+impl<T: Document> Compressed<T> {
+    pub fn compress(document: &T) -> Result<Self, Box<ErrorKind>> {
+        let encoded = bincode::serialize(document)?;
+
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(&encoded)?;
+        let deflated = encoder.finish()?;
+
+        let (deflated, bytes) = if deflated.len() < encoded.len() {
+            (true, deflated)
+        } else {
+            (false, encoded)
+        };
+
+        Ok(Compressed {
+            id: document.id(),
+            deflated,
+            bytes,
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn decompress(&self) -> Result<T, Box<ErrorKind>> {
+        if !self.deflated {
+            return bincode::deserialize(&self.bytes);
+        }
+
+        let mut encoded = vec![];
+        DeflateDecoder::new(&self.bytes[..]).read_to_end(&mut encoded)?;
+
+        bincode::deserialize(&encoded)
+    }
+
+    /// Bytes stored for the document, compressed or not.
+    pub fn stored_len(&self) -> usize {
+        self.bytes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collection::Collection;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tempfile::tempdir;
+
+    static DECODED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Serialize, Clone, Debug, PartialEq)]
+    struct Article {
+        id: u64,
+        body: String,
+    }
+
+    // Counts decoded articles, a decompression being the only way to decode one.
+    impl<'de> Deserialize<'de> for Article {
+        fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let (id, body) = <(u64, String)>::deserialize(deserializer)?;
+            DECODED.fetch_add(1, Ordering::Relaxed);
+            Ok(Article { id, body })
+        }
+    }
+
+    impl HasId for Article {
+        type Id = u64;
+
+        fn id(&self) -> u64 {
+            self.id
+        }
+    }
+
+    #[test]
+    fn reading_one_document_decompresses_only_that_one() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<Compressed<Article>>::new("test", dir_name);
+
+        for id in 0..10 {
+            let article = Article {
+                id,
+                body: format!("article {} ", id).repeat(1_000),
+            };
+            let compressed = Compressed::compress(&article).unwrap();
+            assert!(compressed.deflated);
+            assert!(compressed.stored_len() < 1_000);
+            collection.insert_one(&compressed).unwrap();
+        }
+
+        DECODED.store(0, Ordering::Relaxed);
+        let article = collection.find_by_id(7).unwrap().decompress().unwrap();
+
+        assert_eq!(article.body, "article 7 ".repeat(1_000));
+        assert_eq!(DECODED.load(Ordering::Relaxed), 1);
+    }
+}
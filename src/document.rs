@@ -3,12 +3,55 @@ use serde::Serialize;
 use std::hash::Hash;
 
 pub trait HasId {
-    type Id: PartialEq + Copy + Hash + Eq;
+    type Id: PartialEq + Copy + Hash + Eq + Send + Sync;
     fn id(&self) -> Self::Id;
+
+    /// Version of the document format, bump it when the stored shape changes
+    /// and open the collection with a migration from the previous format.
+    fn schema_version() -> u32
+    where
+        Self: Sized,
+    {
+        0
+    }
+}
+
+/// An id string that couldn't be parsed, with the reason given by the parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdParseError {
+    pub input: String,
+    pub reason: String,
+}
+
+impl IdParseError {
+    pub fn new(input: &str, reason: impl std::fmt::Display) -> Self {
+        IdParseError {
+            input: input.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+/// Documents whose ids can be parsed from strings, such as path segments of a request,
+/// before looking them up.
+pub trait ParseId: HasId {
+    fn parse_id(s: &str) -> Result<Self::Id, IdParseError>;
 }
 
-pub trait Document: Serialize + DeserializeOwned + HasId + std::fmt::Debug + Clone {}
+pub trait Document:
+    Serialize + DeserializeOwned + HasId + std::fmt::Debug + Clone + Send + Sync
+{
+}
 
-impl<T: Serialize + DeserializeOwned + HasId + std::fmt::Debug + Clone> Document for T {}
+impl<T: Serialize + DeserializeOwned + HasId + std::fmt::Debug + Clone + Send + Sync> Document
+    for T
+{
+}
 
 pub type Filter<T> = fn(d: &T) -> bool;
+
+pub type Normalizer<T> = fn(d: T) -> T;
+
+pub type Validator<T> = Box<dyn Fn(&T) -> Result<(), String> + Send + Sync>;
+
+pub type Comparator<T> = Box<dyn Fn(&T, &T) -> std::cmp::Ordering + Send + Sync>;
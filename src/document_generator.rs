@@ -0,0 +1,152 @@
+/// How `DocumentGenerator` picks the size of each payload, in bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeDistribution {
+    /// Every payload takes exactly this size.
+    Fixed(usize),
+    /// Any size from `min` to `max` included, equally likely.
+    Uniform { min: usize, max: usize },
+    /// `small` bytes, except one payload in `large_one_in` on average which takes `large`.
+    Bimodal {
+        small: usize,
+        large: usize,
+        large_one_in: u64,
+    },
+}
+
+/// Reproducible test data: payloads of varied sizes with consecutive ids, the same seed and
+/// settings always generating the same documents. Documents are built from an id and a
+/// payload by the caller, so any document type can be generated.
+#[derive(Debug, Clone)]
+pub struct DocumentGenerator {
+    state: u64,
+    sizes: SizeDistribution,
+    next_id: u64,
+}
+
+impl DocumentGenerator {
+    pub fn new(seed: u64) -> DocumentGenerator {
+        DocumentGenerator {
+            state: seed,
+            sizes: SizeDistribution::Uniform { min: 0, max: 1_000 },
+            next_id: 0,
+        }
+    }
+
+    pub fn with_sizes(mut self, sizes: SizeDistribution) -> DocumentGenerator {
+        self.sizes = sizes;
+        self
+    }
+
+    pub fn with_first_id(mut self, id: u64) -> DocumentGenerator {
+        self.next_id = id;
+        self
+    }
+
+    // splitmix64
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    fn next_size(&mut self) -> usize {
+        match self.sizes {
+            SizeDistribution::Fixed(size) => size,
+            SizeDistribution::Uniform { min, max } => {
+                min + (self.next_u64() % (max.saturating_sub(min) as u64 + 1)) as usize
+            }
+            SizeDistribution::Bimodal {
+                small,
+                large,
+                large_one_in,
+            } => {
+                if self.next_u64().is_multiple_of(large_one_in.max(1)) {
+                    large
+                } else {
+                    small
+                }
+            }
+        }
+    }
+
+    /// Next id and a payload of lowercase letters sized by the distribution.
+    pub fn next_payload(&mut self) -> (u64, String) {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let size = self.next_size();
+        let payload = (0..size)
+            .map(|_| (b'a' + (self.next_u64() % 26) as u8) as char)
+            .collect();
+
+        (id, payload)
+    }
+
+    pub fn generate<T>(&mut self, count: usize, build: impl Fn(u64, String) -> T) -> Vec<T> {
+        (0..count)
+            .map(|_| {
+                let (id, payload) = self.next_payload();
+                build(id, payload)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_generates_the_same_documents() {
+        let sizes = SizeDistribution::Uniform { min: 5, max: 500 };
+        let generate = |seed| {
+            DocumentGenerator::new(seed)
+                .with_sizes(sizes)
+                .generate(50, |id, payload| (id, payload))
+        };
+
+        let documents = generate(7);
+        assert_eq!(documents, generate(7));
+        assert_ne!(documents, generate(8));
+
+        assert_eq!(documents[0].0, 0);
+        assert_eq!(documents[49].0, 49);
+        assert!(documents
+            .iter()
+            .all(|(_, payload)| (5..=500).contains(&payload.len())));
+        let mut lengths: Vec<usize> = documents.iter().map(|(_, payload)| payload.len()).collect();
+        lengths.dedup();
+        assert!(lengths.len() > 1);
+    }
+
+    #[test]
+    fn bimodal_sizes_mix_small_and_large_payloads() {
+        let lengths: Vec<usize> = DocumentGenerator::new(1)
+            .with_first_id(100)
+            .with_sizes(SizeDistribution::Bimodal {
+                small: 10,
+                large: 5_000,
+                large_one_in: 4,
+            })
+            .generate(200, |_, payload| payload.len());
+
+        let large = lengths.iter().filter(|length| **length == 5_000).count();
+        assert!(large > 20 && large < 100);
+        assert_eq!(
+            large + lengths.iter().filter(|length| **length == 10).count(),
+            200
+        );
+    }
+
+    #[test]
+    fn fixed_sizes_generate_payloads_of_one_length() {
+        let documents = DocumentGenerator::new(3)
+            .with_sizes(SizeDistribution::Fixed(64))
+            .generate(10, |id, payload| (id, payload));
+
+        assert!(documents.iter().all(|(_, payload)| payload.len() == 64));
+        assert_ne!(documents[0].1, documents[1].1);
+    }
+}
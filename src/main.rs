@@ -1,15 +1,27 @@
 use serde::{Deserialize, Serialize};
 mod collection;
+mod collection_blob;
 mod collection_file;
+mod collection_history;
+mod collection_in_memory;
 mod collection_indexer;
+mod collection_meta;
 mod collection_page;
+mod collection_scrub;
+mod collection_sequence;
+mod collection_superblock;
+#[cfg(feature = "compression")]
+mod compressed_document;
 mod document;
+#[cfg(any(test, feature = "testing"))]
+mod document_generator;
+mod serialization;
+mod storage;
+mod sync_collection;
 use collection_file::CollectionFile;
 use collection_page::CollectionPage;
 use document::HasId;
 
-const COLLECTION_PAGE_DATA_SIZE: u64 = 62_000;
-
 #[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
 struct MyDocument {
     id: u64,
@@ -0,0 +1,227 @@
+use bincode::{ErrorKind, Options};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntEncoding {
+    Fixint,
+    Varint,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+/// Format documents and pages are encoded with. JSON is self-describing, so documents can
+/// be read back into a type with fewer fields, at the cost of larger pages.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Bincode,
+    Json,
+}
+
+// Room kept in every JSON page for the field names of the header and the braces around it.
+const JSON_PAGE_OVERHEAD: u64 = 256;
+
+/// Bincode options used for every serialize, deserialize and size computation of a collection.
+/// The default matches `bincode::serialize`: fixint, little endian, no byte limit.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SerializationConfig {
+    codec: Codec,
+    int_encoding: IntEncoding,
+    endianness: Endianness,
+    limit: Option<u64>,
+}
+
+impl Default for SerializationConfig {
+    fn default() -> Self {
+        SerializationConfig {
+            codec: Codec::Bincode,
+            int_encoding: IntEncoding::Fixint,
+            endianness: Endianness::Little,
+            limit: None,
+        }
+    }
+}
+
+// bincode::Options is implemented by a different type for every combination of options,
+// so each combination is expanded into its own arm before running the body.
+macro_rules! with_options {
+    ($config:expr, $options:ident => $body:expr) => {{
+        let options = bincode::DefaultOptions::new().allow_trailing_bytes();
+        match $config.int_encoding {
+            IntEncoding::Fixint => {
+                with_options!(@endianness $config, options.with_fixint_encoding(), $options => $body)
+            }
+            IntEncoding::Varint => {
+                with_options!(@endianness $config, options.with_varint_encoding(), $options => $body)
+            }
+        }
+    }};
+    (@endianness $config:expr, $partial:expr, $options:ident => $body:expr) => {
+        match $config.endianness {
+            Endianness::Little => {
+                with_options!(@limit $config, $partial.with_little_endian(), $options => $body)
+            }
+            Endianness::Big => {
+                with_options!(@limit $config, $partial.with_big_endian(), $options => $body)
+            }
+        }
+    };
+    (@limit $config:expr, $partial:expr, $options:ident => $body:expr) => {
+        match $config.limit {
+            Some(limit) => {
+                let $options = $partial.with_limit(limit);
+                $body
+            }
+            None => {
+                let $options = $partial.with_no_limit();
+                $body
+            }
+        }
+    };
+}
+
+fn json_error(err: serde_json::Error) -> Box<ErrorKind> {
+    Box::new(ErrorKind::Custom(err.to_string()))
+}
+
+impl SerializationConfig {
+    /// The bincode options are ignored by the JSON codec.
+    pub fn with_codec(mut self, codec: Codec) -> Self {
+        self.codec = codec;
+        self
+    }
+
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    pub fn is_self_describing(&self) -> bool {
+        self.codec == Codec::Json
+    }
+
+    /// Bytes of a page taken by the encoding itself on top of the bincode page header.
+    pub fn page_overhead(&self) -> u64 {
+        match self.codec {
+            Codec::Bincode => 0,
+            Codec::Json => JSON_PAGE_OVERHEAD,
+        }
+    }
+
+    /// Bytes separating two documents of a page.
+    pub fn document_separator_size(&self) -> u64 {
+        match self.codec {
+            Codec::Bincode => 0,
+            Codec::Json => 1,
+        }
+    }
+
+    pub fn with_int_encoding(mut self, int_encoding: IntEncoding) -> Self {
+        self.int_encoding = int_encoding;
+        self
+    }
+
+    pub fn with_endianness(mut self, endianness: Endianness) -> Self {
+        self.endianness = endianness;
+        self
+    }
+
+    pub fn with_limit(mut self, limit: u64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn serialize<S: Serialize + ?Sized>(&self, value: &S) -> Result<Vec<u8>, Box<ErrorKind>> {
+        match self.codec {
+            Codec::Bincode => with_options!(self, options => options.serialize(value)),
+            Codec::Json => serde_json::to_vec(value).map_err(json_error),
+        }
+    }
+
+    pub fn deserialize<D: DeserializeOwned>(&self, bytes: &[u8]) -> Result<D, Box<ErrorKind>> {
+        match self.codec {
+            Codec::Bincode => with_options!(self, options => options.deserialize(bytes)),
+            // Pages are padded with zeros, so only the first JSON value is read.
+            Codec::Json => serde_json::Deserializer::from_slice(bytes)
+                .into_iter()
+                .next()
+                .unwrap_or_else(|| Err(serde::de::Error::custom("no JSON value")))
+                .map_err(json_error),
+        }
+    }
+
+    pub fn serialized_size<S: Serialize + ?Sized>(&self, value: &S) -> Result<u64, Box<ErrorKind>> {
+        match self.codec {
+            Codec::Bincode => with_options!(self, options => options.serialized_size(value)),
+            Codec::Json => serde_json::to_vec(value)
+                .map(|encoded| encoded.len() as u64)
+                .map_err(json_error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_config_matches_bincode_defaults() {
+        let config = SerializationConfig::default();
+        let value: (u64, String) = (1, String::from("test"));
+
+        assert_eq!(
+            config.serialize(&value).unwrap(),
+            bincode::serialize(&value).unwrap()
+        );
+        assert_eq!(
+            config.serialized_size(&value).unwrap(),
+            bincode::serialized_size(&value).unwrap()
+        );
+    }
+
+    #[test]
+    fn varint_big_endian_round_trip() {
+        let config = SerializationConfig::default()
+            .with_int_encoding(IntEncoding::Varint)
+            .with_endianness(Endianness::Big);
+        let value: (u64, u32) = (1, 300);
+
+        let encoded = config.serialize(&value).unwrap();
+
+        assert_eq!(
+            encoded.len() as u64,
+            config.serialized_size(&value).unwrap()
+        );
+        assert!(encoded.len() < bincode::serialize(&value).unwrap().len());
+        assert_eq!(config.deserialize::<(u64, u32)>(&encoded).unwrap(), value);
+    }
+
+    #[test]
+    fn limit_is_enforced() {
+        let config = SerializationConfig::default().with_limit(4);
+
+        assert!(config.serialize(&1u64).is_err());
+    }
+
+    #[test]
+    fn json_round_trip_ignores_trailing_zeros() {
+        let config = SerializationConfig::default().with_codec(Codec::Json);
+        let value: (u64, String) = (1, String::from("test"));
+
+        let mut encoded = config.serialize(&value).unwrap();
+        assert_eq!(encoded, br#"[1,"test"]"#);
+        assert_eq!(
+            config.serialized_size(&value).unwrap(),
+            encoded.len() as u64
+        );
+
+        encoded.extend_from_slice(&[0u8; 16]);
+        assert_eq!(
+            config.deserialize::<(u64, String)>(&encoded).unwrap(),
+            value
+        );
+    }
+}
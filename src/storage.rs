@@ -0,0 +1,149 @@
+use std::fs::File;
+use std::io;
+use std::os::unix::prelude::FileExt;
+
+/// Positional byte storage backing a collection file. Reads take `&self` and are positional,
+/// so implementations must support concurrent reads from several threads.
+pub trait Storage: std::fmt::Debug + Send + Sync {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize>;
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()>;
+    fn sync_data(&self) -> io::Result<()>;
+    fn len(&self) -> io::Result<u64>;
+
+    /// Reads exactly `buf.len()` bytes, going on after short reads, and fails with
+    /// `UnexpectedEof` when the storage ends first.
+    fn read_exact_at(&self, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+        while !buf.is_empty() {
+            match self.read_at(buf, offset) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "storage ended before the buffer was filled",
+                    ))
+                }
+                Ok(n) => {
+                    buf = &mut buf[n..];
+                    offset += n as u64;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Storage for File {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        FileExt::read_at(self, buf, offset)
+    }
+
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        FileExt::write_all_at(self, buf, offset)
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        File::sync_data(self)
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+}
+
+/// When a `FaultyStorage` injects a write fault, writes being counted from 1.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Clone, Copy)]
+enum WriteFault {
+    /// Every write after the first `n` fails, simulating a crash.
+    FailAfter(usize),
+    /// Only the `n`th write fails.
+    FailNth(usize),
+    /// The `n`th write stores half of its bytes, then panics.
+    PanicAt(usize),
+}
+
+/// Storage over a file that injects faults, to test crash safety and error handling.
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug)]
+pub struct FaultyStorage {
+    inner: File,
+    writes: std::sync::atomic::AtomicUsize,
+    write_fault: WriteFault,
+    short_reads: bool,
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl FaultyStorage {
+    /// Fails every write once `writes` writes went through.
+    pub fn failing_after(inner: File, writes: usize) -> FaultyStorage {
+        Self::with_write_fault(inner, WriteFault::FailAfter(writes))
+    }
+
+    /// Fails the `n`th write only, counting from 1.
+    pub fn failing_nth_write(inner: File, n: usize) -> FaultyStorage {
+        Self::with_write_fault(inner, WriteFault::FailNth(n))
+    }
+
+    /// Panics in the middle of the `n`th write, leaving it torn.
+    pub fn panicking_at_write(inner: File, n: usize) -> FaultyStorage {
+        Self::with_write_fault(inner, WriteFault::PanicAt(n))
+    }
+
+    /// Reads return at most half of the bytes asked for, as positional reads are allowed to.
+    pub fn with_short_reads(mut self) -> FaultyStorage {
+        self.short_reads = true;
+        self
+    }
+
+    fn with_write_fault(inner: File, write_fault: WriteFault) -> FaultyStorage {
+        FaultyStorage {
+            inner,
+            writes: std::sync::atomic::AtomicUsize::new(0),
+            write_fault,
+            short_reads: false,
+        }
+    }
+}
+
+#[cfg(any(test, feature = "testing"))]
+impl Storage for FaultyStorage {
+    fn read_at(&self, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+        if self.short_reads && buf.len() > 1 {
+            let half = buf.len() / 2;
+            return Storage::read_at(&self.inner, &mut buf[..half], offset);
+        }
+
+        Storage::read_at(&self.inner, buf, offset)
+    }
+
+    fn write_all_at(&self, buf: &[u8], offset: u64) -> io::Result<()> {
+        use std::sync::atomic::Ordering;
+
+        let write = self.writes.fetch_add(1, Ordering::SeqCst) + 1;
+        match self.write_fault {
+            WriteFault::FailAfter(n) if write > n => {
+                return Err(io::Error::other("injected write failure"));
+            }
+            WriteFault::FailNth(n) if write == n => {
+                return Err(io::Error::other("injected write failure"));
+            }
+            WriteFault::PanicAt(n) if write == n => {
+                Storage::write_all_at(&self.inner, &buf[..buf.len() / 2], offset)?;
+                panic!("injected panic during write {}", write);
+            }
+            _ => {}
+        }
+
+        Storage::write_all_at(&self.inner, buf, offset)
+    }
+
+    fn sync_data(&self) -> io::Result<()> {
+        Storage::sync_data(&self.inner)
+    }
+
+    fn len(&self) -> io::Result<u64> {
+        Storage::len(&self.inner)
+    }
+}
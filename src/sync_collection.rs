@@ -0,0 +1,540 @@
+use std::ops::Deref;
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::collection::{Collection, CollectionError};
+use crate::document::{Document, Filter, HasId};
+
+/// A `Collection` shareable between threads (behind an `Arc`) that can also be written to.
+/// Reads run concurrently, a write waits for in-flight reads and blocks new ones.
+pub struct SyncCollection<T: Document> {
+    collection: RwLock<Collection<T>>,
+}
+
+impl<T: Document> SyncCollection<T> {
+    pub fn new(collection: Collection<T>) -> SyncCollection<T> {
+        SyncCollection {
+            collection: RwLock::new(collection),
+        }
+    }
+
+    // Poisoning is ignored unless the collection holds unwritten changes, see
+    // `recover_poisoned`.
+    pub fn read(&self) -> RwLockReadGuard<'_, Collection<T>> {
+        self.collection.read().unwrap_or_else(recover_poisoned)
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, Collection<T>> {
+        self.collection.write().unwrap_or_else(recover_poisoned)
+    }
+
+    pub fn find_by_id(&self, id: <T as HasId>::Id) -> Option<T> {
+        self.read().find_by_id(id)
+    }
+
+    pub fn find_by(&self, filter: Filter<T>) -> Result<Vec<T>, CollectionError> {
+        self.read().find_by(filter)
+    }
+
+    pub fn insert_one(&self, doc: &T) -> Result<(), CollectionError> {
+        self.write().insert_one(doc)
+    }
+
+    pub fn update_one(&self, doc: &T) -> Result<(), CollectionError> {
+        self.write().update_one(doc)
+    }
+
+    pub fn upsert_one(&self, doc: &T) -> Result<(), CollectionError> {
+        self.write().upsert_one(doc)
+    }
+
+    pub fn delete_one(&self, id: <T as HasId>::Id) -> Result<T, CollectionError> {
+        self.write().delete_one(id)
+    }
+
+    /// Increments a counter of a document, no other write can happen between the read and
+    /// the write back.
+    pub fn increment_field(
+        &self,
+        id: <T as HasId>::Id,
+        delta: i64,
+        field: impl Fn(&mut T) -> &mut i64,
+    ) -> Result<i64, CollectionError> {
+        self.write().increment_field(id, delta, field)
+    }
+}
+
+/// Takes the lock of a collection another thread panicked while holding. Writes that reach
+/// the file before returning can't be left half done in memory, so the collection is used
+/// as is, unless it holds an open batch or unflushed write-back pages a panic may have left
+/// half updated: the poisoning then panics in turn.
+fn recover_poisoned<T: Document, G: Deref<Target = Collection<T>>>(poisoned: PoisonError<G>) -> G {
+    let collection = poisoned.into_inner();
+    if collection.has_unwritten_changes() {
+        panic!("a thread panicked while holding the collection lock with unwritten changes");
+    }
+
+    collection
+}
+
+/// A `Collection` appended to from many threads (behind an `Arc`). `insert_one` writes
+/// under the lock right away, `insert_batched` only queues the document and the thread
+/// filling the queue writes it in one batch, so concurrent producers share page writes.
+/// Queued documents still waiting are written when the writer is dropped.
+pub struct SharedWriter<T: Document> {
+    collection: Mutex<Collection<T>>,
+    queue: Mutex<Vec<T>>,
+    rejected: Mutex<Vec<(T, CollectionError)>>,
+    batch_size: usize,
+}
+
+impl<T: Document> SharedWriter<T> {
+    pub fn new(collection: Collection<T>) -> SharedWriter<T> {
+        SharedWriter {
+            collection: Mutex::new(collection),
+            queue: Mutex::new(vec![]),
+            rejected: Mutex::new(vec![]),
+            batch_size: 64,
+        }
+    }
+
+    /// Number of queued documents that makes `insert_batched` write the queue, 64 by default.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    // Same as for `SyncCollection`, see `recover_poisoned`.
+    pub fn lock(&self) -> MutexGuard<'_, Collection<T>> {
+        self.collection.lock().unwrap_or_else(recover_poisoned)
+    }
+
+    fn lock_queue(&self) -> MutexGuard<'_, Vec<T>> {
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_rejected(&self) -> MutexGuard<'_, Vec<(T, CollectionError)>> {
+        self.rejected.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn insert_one(&self, doc: &T) -> Result<(), CollectionError> {
+        self.lock().insert_one(doc)
+    }
+
+    /// Queues a document, writing the whole queue once it holds `batch_size` documents. A
+    /// document refused when the queue is written (a duplicate, a failed validation, ...)
+    /// doesn't stop the others: the caller gets the error for its own document, the errors
+    /// for documents queued by other threads are kept for `take_rejected`.
+    pub fn insert_batched(&self, doc: T) -> Result<(), CollectionError> {
+        let batch = {
+            let mut queue = self.lock_queue();
+            queue.push(doc);
+            if queue.len() < self.batch_size {
+                return Ok(());
+            }
+            std::mem::take(&mut *queue)
+        };
+
+        // The document of the caller is the last one of the batch.
+        let own = batch.len() - 1;
+        let mut own_error = None;
+        for (index, document, error) in self.write_batch(batch)? {
+            if index == own {
+                own_error = Some(error);
+            } else {
+                self.lock_rejected().push((document, error));
+            }
+        }
+
+        match own_error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the documents still queued by `insert_batched`, the documents refused are kept
+    /// for `take_rejected`.
+    pub fn flush(&self) -> Result<(), CollectionError> {
+        let batch = std::mem::take(&mut *self.lock_queue());
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        for (_, document, error) in self.write_batch(batch)? {
+            self.lock_rejected().push((document, error));
+        }
+
+        Ok(())
+    }
+
+    /// Documents queued by `insert_batched` that were refused when their batch was written,
+    /// with the reason, except for the documents whose caller got the error back.
+    pub fn take_rejected(&self) -> Vec<(T, CollectionError)> {
+        std::mem::take(&mut *self.lock_rejected())
+    }
+
+    /// Checks each document on its own, then writes the accepted ones together, returning
+    /// the refused ones with their position in `batch`. When the write itself fails the
+    /// accepted documents go back to the front of the queue for the next write; any of them
+    /// already written by then is refused as a duplicate on the retry.
+    fn write_batch(
+        &self,
+        batch: Vec<T>,
+    ) -> Result<Vec<(usize, T, CollectionError)>, CollectionError> {
+        let mut collection = self.lock();
+        let mut accepted = Vec::with_capacity(batch.len());
+        let mut refused = vec![];
+
+        collection.begin_batch();
+        for (index, document) in batch.into_iter().enumerate() {
+            match collection.insert_one(&document) {
+                Ok(()) => accepted.push(document),
+                Err(error) => refused.push((index, document, error)),
+            }
+        }
+
+        if let Err(error) = collection.commit_batch() {
+            let mut queue = self.lock_queue();
+            accepted.append(&mut queue);
+            *queue = accepted;
+            return Err(error);
+        }
+
+        Ok(refused)
+    }
+}
+
+impl<T: Document> Drop for SharedWriter<T> {
+    // Errors can't be reported from here, call `flush` to see them.
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::collection_file::CollectionFile;
+    use crate::document::HasId;
+    use serde_derive::{Deserialize, Serialize};
+    use std::sync::Arc;
+    use std::thread;
+    use tempfile::tempdir;
+
+    #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+    struct MyDocument {
+        id: u64,
+        name: String,
+    }
+
+    impl HasId for MyDocument {
+        type Id = u64;
+
+        fn id(&self) -> u64 {
+            self.id
+        }
+    }
+
+    fn assert_send_sync<S: Send + Sync>() {}
+
+    #[test]
+    fn test_collections_are_send_and_sync() {
+        assert_send_sync::<Collection<MyDocument>>();
+        assert_send_sync::<CollectionFile<MyDocument>>();
+        assert_send_sync::<SyncCollection<MyDocument>>();
+    }
+
+    #[test]
+    fn test_concurrent_readers_on_shared_collection() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let mut collection = Collection::<MyDocument>::new("test", dir_name);
+
+        for id in 0..100 {
+            collection
+                .insert_one(&MyDocument {
+                    id,
+                    name: format!("test{}", id),
+                })
+                .unwrap();
+        }
+
+        let collection = Arc::new(collection);
+        let readers: Vec<_> = (0..4)
+            .map(|reader| {
+                let collection = Arc::clone(&collection);
+                thread::spawn(move || {
+                    for id in (reader..100).step_by(4) {
+                        assert_eq!(
+                            collection.find_by_id(id).unwrap().name,
+                            format!("test{}", id)
+                        );
+                    }
+                })
+            })
+            .collect();
+
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_sync_collection_reads_and_writes_from_threads() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let collection = Arc::new(SyncCollection::new(Collection::<MyDocument>::new(
+            "test", dir_name,
+        )));
+
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let collection = Arc::clone(&collection);
+                thread::spawn(move || {
+                    for id in (writer * 10)..(writer * 10 + 10) {
+                        collection
+                            .insert_one(&MyDocument {
+                                id,
+                                name: format!("test{}", id),
+                            })
+                            .unwrap();
+                        assert!(collection.find_by_id(id).is_some());
+                    }
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(collection.find_by(|_| true).unwrap().len(), 40);
+
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let collection = Arc::clone(&collection);
+                thread::spawn(move || {
+                    let id = writer * 10;
+                    collection
+                        .update_one(&MyDocument {
+                            id,
+                            name: "updated".to_string(),
+                        })
+                        .unwrap();
+                    collection
+                        .upsert_one(&MyDocument {
+                            id: 100 + writer,
+                            name: "upserted".to_string(),
+                        })
+                        .unwrap();
+                    assert_eq!(collection.delete_one(id + 1).unwrap().id, id + 1);
+                })
+            })
+            .collect();
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        assert_eq!(collection.find_by(|_| true).unwrap().len(), 40);
+        assert_eq!(
+            collection
+                .find_by(|document| document.name == "updated")
+                .unwrap()
+                .len(),
+            4
+        );
+        assert_eq!(collection.find_by_id(102).unwrap().name, "upserted");
+        assert!(collection.find_by_id(21).is_none());
+    }
+
+    #[test]
+    fn test_shared_writer_keeps_every_insert_from_threads() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let writer = Arc::new(
+            SharedWriter::new(Collection::<MyDocument>::new("test", dir_name)).with_batch_size(16),
+        );
+
+        // Even producers insert one by one, odd ones through the queue.
+        let producers: Vec<_> = (0..6)
+            .map(|producer| {
+                let writer = Arc::clone(&writer);
+                thread::spawn(move || {
+                    for id in (producer * 50)..(producer * 50 + 50) {
+                        let document = MyDocument {
+                            id,
+                            name: format!("test{}", id),
+                        };
+                        if producer % 2 == 0 {
+                            writer.insert_one(&document).unwrap();
+                        } else {
+                            writer.insert_batched(document).unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+
+        // Dropping the writer writes the documents still queued.
+        drop(Arc::into_inner(writer).unwrap());
+
+        let reopened = Collection::<MyDocument>::new("test", dir_name);
+        assert_eq!(reopened.find_by(|_| true).unwrap().len(), 300);
+        for id in 0..300 {
+            assert_eq!(reopened.find_by_id(id).unwrap().name, format!("test{}", id));
+        }
+    }
+
+    #[test]
+    fn test_shared_writer_keeps_the_batch_around_a_duplicate() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let writer = Arc::new(
+            SharedWriter::new(Collection::<MyDocument>::new("test", dir_name)).with_batch_size(8),
+        );
+        writer
+            .insert_one(&MyDocument {
+                id: 1_000,
+                name: "stored".to_string(),
+            })
+            .unwrap();
+
+        // One producer queues a document with an id already stored, among the others.
+        let producers: Vec<_> = (0..4)
+            .map(|producer| {
+                let writer = Arc::clone(&writer);
+                thread::spawn(move || {
+                    let mut errors = 0;
+                    for id in (producer * 10)..(producer * 10 + 10) {
+                        let id = if id == 15 { 1_000 } else { id };
+                        let document = MyDocument {
+                            id,
+                            name: format!("test{}", id),
+                        };
+                        match writer.insert_batched(document) {
+                            Ok(()) => {}
+                            Err(CollectionError::DuplicateError) => errors += 1,
+                            Err(e) => panic!("{:?}", e),
+                        }
+                    }
+                    errors
+                })
+            })
+            .collect();
+
+        let mut errors: usize = producers.into_iter().map(|p| p.join().unwrap()).sum();
+        writer.flush().unwrap();
+        let rejected = writer.take_rejected();
+        errors += rejected.len();
+
+        assert_eq!(errors, 1);
+        assert!(rejected.iter().all(|(document, error)| document.id == 1_000
+            && matches!(error, CollectionError::DuplicateError)));
+        let collection = writer.lock();
+        assert_eq!(collection.find_by(|_| true).unwrap().len(), 40);
+        assert_eq!(collection.find_by_id(1_000).unwrap().name, "stored");
+        for id in (0..40).filter(|id| *id != 15) {
+            assert_eq!(
+                collection.find_by_id(id).unwrap().name,
+                format!("test{}", id)
+            );
+        }
+    }
+
+    #[test]
+    fn test_poisoned_lock_is_recovered_only_without_unwritten_changes() {
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let collection = Arc::new(SyncCollection::new(Collection::<MyDocument>::new(
+            "test", dir_name,
+        )));
+        collection
+            .insert_one(&MyDocument {
+                id: 1,
+                name: "test1".to_string(),
+            })
+            .unwrap();
+
+        let panicking = Arc::clone(&collection);
+        let writer = thread::spawn(move || {
+            let _guard = panicking.write();
+            panic!("writer panics holding the lock");
+        });
+        assert!(writer.join().is_err());
+        assert_eq!(collection.find_by_id(1).unwrap().name, "test1");
+
+        let panicking = Arc::clone(&collection);
+        let writer = thread::spawn(move || {
+            panicking.write().begin_batch();
+            let _guard = panicking.write();
+            panic!("writer panics with a batch open");
+        });
+        assert!(writer.join().is_err());
+        let poisoned =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| collection.find_by_id(1)));
+        assert!(poisoned.is_err());
+    }
+
+    #[test]
+    fn test_increment_field_from_threads() {
+        #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+        struct Counter {
+            id: u64,
+            hits: i64,
+        }
+
+        impl HasId for Counter {
+            type Id = u64;
+
+            fn id(&self) -> u64 {
+                self.id
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let binding = dir.into_path();
+        let dir_name = binding.to_str().unwrap();
+        let collection = Arc::new(SyncCollection::new(Collection::<Counter>::new(
+            "test", dir_name,
+        )));
+        collection.insert_one(&Counter { id: 1, hits: 0 }).unwrap();
+
+        let incrementers: Vec<_> = (0..4)
+            .map(|_| {
+                let collection = Arc::clone(&collection);
+                thread::spawn(move || {
+                    for _ in 0..25 {
+                        collection
+                            .increment_field(1, 2, |counter| &mut counter.hits)
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        for incrementer in incrementers {
+            incrementer.join().unwrap();
+        }
+
+        assert_eq!(collection.find_by_id(1).unwrap().hits, 200);
+        assert_eq!(
+            collection
+                .increment_field(1, -50, |counter| &mut counter.hits)
+                .unwrap(),
+            150
+        );
+        assert!(matches!(
+            collection.increment_field(2, 1, |counter| &mut counter.hits),
+            Err(CollectionError::NotFoundError)
+        ));
+    }
+}